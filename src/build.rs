@@ -1,18 +1,44 @@
-use std::{env, ffi::OsStr, fmt::Write, path::PathBuf, sync::LazyLock};
+use std::{
+    env,
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
-use clap::ValueEnum;
+use anyhow::{Context, bail};
+use futures_util::{StreamExt, TryStreamExt, stream};
+use heck::ToSnakeCase;
 use sha2::{Digest, Sha256};
 use strum::IntoEnumIterator;
-use tokio::{fs, process::Command};
+use tokio::fs;
 
-use crate::run;
+use crate::{
+    PublishOptions, Release, ReleaseManifest, WheelManifest, plan, provenance::BuildProvenance,
+    publish::parse_hashes, runner::BuildLog,
+};
 
 mod build_tools;
-mod pydantic;
-mod regex;
+mod cache;
+mod clean;
+mod flags;
+mod maturin;
+mod meson;
+mod patches;
+mod range;
+mod recipe;
+mod scikit_build;
+mod setuptools;
+mod source;
 mod wheels;
 
 pub use build_tools::PythonVersion;
+pub use clean::{CleanScope, clean};
+pub use range::build_range;
+pub use recipe::{Backend, MaturinOptions, MesonOptions, Patch, Recipe, Recipes};
+pub use source::{Source, metadata_field};
+
+use cache::BuildInputs;
+use source::{PreparedSource, copy_source_tree, source_date_epoch};
 
 /// Current directory of this repository
 pub static REPO_DIR: LazyLock<PathBuf> =
@@ -37,98 +63,419 @@ pub async fn install_build_tools() -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Projects that we support builds for
-#[derive(Debug, Clone, Copy, ValueEnum, strum::Display)]
-#[strum(serialize_all = "kebab-case")]
-pub enum SupportedProjects {
-    /// <https://pypi.org/project/pydantic-core/>
-    PydanticCore,
-    /// <https://pypi.org/project/regex/>
-    Regex,
+/// Settings shared by every build
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// Where to unpack and build the sources. Defaults to "packages" directory in this repository
+    pub output_dir: Option<PathBuf>,
+    /// Python versions to build wheels for
+    pub python_versions: Vec<PythonVersion>,
+    /// How many Python versions to build at once. Defaults to all of them
+    pub jobs: Option<usize>,
+    /// Build every wheel again, instead of reusing one a previous run built from the same inputs
+    pub rebuild: bool,
+    /// Build even if the version is outside of the recipe's supported versions
+    pub force: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            output_dir: None,
+            python_versions: PythonVersion::iter().collect(),
+            jobs: None,
+            rebuild: false,
+            force: false,
+        }
+    }
 }
 
-/// Build a given package into a WASI wheel.
+/// Build a given package into a WASI wheel, and publish it if publish options are given.
 ///
 /// # Errors
 /// If the build fails.
 pub async fn build_and_publish(
-    project: SupportedProjects,
-    release_version: &str,
-    output_dir: Option<PathBuf>,
-    python_versions: &[PythonVersion],
-    publish_notes: Option<String>,
-    replace_existing_release: bool,
+    recipe: &Recipe,
+    source: &Source,
+    options: &BuildOptions,
+    publish: Option<&PublishOptions>,
 ) -> anyhow::Result<()> {
-    let wheel_paths = build(project, release_version, output_dir, python_versions).await?;
-
-    if let Some(notes) = publish_notes {
-        publish_release(
-            project,
-            release_version,
-            &wheel_paths,
-            &notes,
-            replace_existing_release,
-        )
-        .await?;
+    // Boxed, since the build's future is too large to keep on the stack
+    let output = Box::pin(build_output(recipe, source, options)).await?;
+
+    if let Some(publish) = publish {
+        let Source::Release(release_version) = source else {
+            bail!("Only released versions can be published");
+        };
+        publish_release(recipe, release_version, &output, publish).await?;
     }
 
     Ok(())
 }
 
+/// Wheels built from a source, and how they were built
+#[derive(Debug)]
+struct BuildOutput {
+    /// Built wheels, one for each Python version unless they are pure Python
+    wheel_paths: Vec<PathBuf>,
+    /// Source tree the wheels were built from
+    source: PreparedSource,
+    /// Every command that ran, in the order they finished
+    commands: Vec<String>,
+}
+
+/// Build wheels for each Python version, and collect them in the `dist` directory of the
+/// prepared source tree. Each Python version is built in its own copy of the source tree, so
+/// the builds can run concurrently.
+///
+/// The output of each step is captured under `logs` in the output directory, along with a
+/// `report.json` of the whole build.
 pub async fn build(
-    project: SupportedProjects,
-    release_version: &str,
-    output_dir: Option<PathBuf>,
-    python_versions: &[PythonVersion],
+    recipe: &Recipe,
+    source: &Source,
+    options: &BuildOptions,
 ) -> anyhow::Result<Vec<PathBuf>> {
+    Ok(build_output(recipe, source, options).await?.wheel_paths)
+}
+
+/// Build wheels like [`build`], keeping track of the source and commands they were built with
+async fn build_output(
+    recipe: &Recipe,
+    source: &Source,
+    options: &BuildOptions,
+) -> anyhow::Result<BuildOutput> {
+    let log_dir = options
+        .output_dir
+        .as_ref()
+        .unwrap_or(&PACKAGES_DIR)
+        .join("logs")
+        .join(format!(
+            "{}-{}",
+            recipe.name.to_snake_case(),
+            source.label()
+        ));
+    let log = BuildLog::create(log_dir).await?;
+
+    let result = log
+        .clone()
+        .scope(build_wheels(recipe, source, options, &log))
+        .await;
+    if !plan::is_planning() {
+        let wheel_paths = result
+            .as_ref()
+            .map(|(_, wheel_paths)| wheel_paths.as_slice());
+        match log.write_report(&recipe.name, wheel_paths).await {
+            Ok(report) => eprintln!("Build report written to {}", report.display()),
+            Err(error) => eprintln!("Unable to write build report: {error:#}"),
+        }
+    }
+
+    let (source, wheel_paths) = result?;
+    Ok(BuildOutput {
+        wheel_paths,
+        source,
+        commands: log.commands(),
+    })
+}
+
+/// Prepare the source and build the wheels for every Python version
+async fn build_wheels(
+    recipe: &Recipe,
+    source: &Source,
+    options: &BuildOptions,
+    log: &BuildLog,
+) -> anyhow::Result<(PreparedSource, Vec<PathBuf>)> {
+    let source = source
+        .prepare(recipe, options.output_dir.clone(), options.force)
+        .await?;
+    let dist_dir = source.package_dir.join("dist");
+    plan::create_dir_all(&dist_dir).await?;
+
+    // One at a time when planning, to keep the steps of each version together
+    let jobs = if plan::is_planning() {
+        1
+    } else {
+        options.jobs.unwrap_or(options.python_versions.len()).max(1)
+    };
+    let built = stream::iter(&options.python_versions)
+        .map(|&python_version| {
+            log.with_prefix(format!("py{python_version}-"))
+                .scope(build_python_version(
+                    recipe,
+                    &source,
+                    python_version,
+                    &dist_dir,
+                    options.rebuild,
+                ))
+        })
+        .buffered(jobs)
+        .try_collect::<Vec<_>>()
+        .await?;
+
     let mut wheel_paths = vec![];
+    for mut wheel_path in built {
+        if let Some(local_label) = source.local_label.as_ref().filter(|_| !plan::is_planning()) {
+            wheel_path = wheels::relabel_wheel(wheel_path, local_label).await?;
+        }
+        // Pure Python wheels are shared across Python versions
+        if !wheel_paths.contains(&wheel_path) {
+            wheel_paths.push(wheel_path);
+        }
+    }
+
+    Ok((source, wheel_paths))
+}
+
+/// Build the wheel for a single Python version in an isolated copy of the source tree, and copy
+/// it into the shared dist directory. Unless `rebuild` is set, a wheel built from the same inputs
+/// is reused.
+async fn build_python_version(
+    recipe: &Recipe,
+    source: &PreparedSource,
+    python_version: PythonVersion,
+    dist_dir: &Path,
+    rebuild: bool,
+) -> anyhow::Result<PathBuf> {
+    let package_dir = &source.package_dir;
+    let version = &source.version;
+
+    // Next to the package directory, which may have a trailing slash
+    let work_dir = package_dir.with_file_name(format!(
+        "{}-py{python_version}",
+        package_dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    ));
+
+    // Already built by a previous run with the same inputs
+    let inputs = BuildInputs::new(recipe, source, python_version, &work_dir).await?;
+    let candidates = [wheels::wheel_path(
+        &recipe.name,
+        python_version,
+        package_dir,
+        version,
+    )]
+    .into_iter()
+    .chain(wheels::pure_python_wheel_path(
+        &recipe.name,
+        package_dir,
+        version,
+    )?);
+    if !rebuild && let Some(wheel) = inputs.cached_wheel(candidates).await? {
+        return Ok(wheel);
+    }
+    copy_source_tree(package_dir, &work_dir).await?;
+
+    let built = match recipe.backend {
+        Backend::Maturin => maturin::build(recipe, python_version, &work_dir, version).await?,
+        Backend::Setuptools => {
+            setuptools::build(
+                recipe,
+                python_version,
+                &work_dir,
+                version,
+                &setuptools::Compilers::Environment,
+            )
+            .await?
+        }
+        Backend::MesonPython => meson::build(recipe, python_version, &work_dir, version).await?,
+        Backend::ScikitBuildCore => {
+            scikit_build::build(recipe, python_version, &work_dir, version).await?
+        }
+    };
+    if plan::is_planning() {
+        return Ok(built);
+    }
+    wheels::normalize_wheel(&built, source_date_epoch(package_dir)).await?;
+
+    let wheel = dist_dir.join(built.file_name().unwrap_or_default());
+    fs::copy(&built, &wheel).await?;
+    inputs.write_manifest(&wheel).await?;
+
+    Ok(wheel)
+}
+
+/// Build any project from its sdist with a generic recipe for the given backend, and report what
+/// kind of wheel was produced.
+///
+/// # Errors
+/// If the build fails.
+pub async fn build_generic(
+    project: &str,
+    backend: Backend,
+    release_version: &str,
+    options: &BuildOptions,
+) -> anyhow::Result<()> {
+    let recipe = Recipe::generic(project, backend);
+    let source = Source::Release(release_version.to_owned());
+    let wheel_paths = build(&recipe, &source, options).await?;
+
+    for wheel_path in wheel_paths {
+        let contents = wheels::inspect_wheel(&wheel_path).await?;
+        let filename = wheel_path.file_name().unwrap_or_default().to_string_lossy();
+
+        if contents.is_pure_python() {
+            println!("{filename}: pure Python, no extension modules");
+            continue;
+        }
+        println!(
+            "{filename}: {} WebAssembly extension module(s)",
+            contents.wasm_modules.len()
+        );
+        for module in &contents.wasm_modules {
+            println!("  {module}");
+        }
+        if !contents.native_modules.is_empty() {
+            bail!(
+                "{filename} contains extension modules that were not compiled to WebAssembly: {}",
+                contents.native_modules.join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
 
-    for python_version in python_versions {
-        let wheel_path = match project {
-            SupportedProjects::PydanticCore => {
-                pydantic::build(*python_version, release_version, output_dir.clone()).await?
-            }
-            SupportedProjects::Regex => {
-                regex::build(*python_version, release_version, output_dir.clone()).await?
-            }
+/// Build the wheels twice, each in its own output directory under `reproducible`, and check that
+/// both builds produced identical wheels. Neither build reuses cached wheels, so both really run.
+///
+/// # Errors
+/// If either build fails, or the wheels differ.
+pub async fn verify_reproducible(
+    recipe: &Recipe,
+    source: &Source,
+    options: &BuildOptions,
+) -> anyhow::Result<()> {
+    let reproducible_dir = options
+        .output_dir
+        .as_ref()
+        .unwrap_or(&PACKAGES_DIR)
+        .join("reproducible");
+    let options_in = |name: &str| BuildOptions {
+        output_dir: Some(reproducible_dir.join(name)),
+        rebuild: true,
+        ..options.clone()
+    };
+    let first = Box::pin(build(recipe, source, &options_in("first"))).await?;
+    let second = Box::pin(build(recipe, source, &options_in("second"))).await?;
+
+    let mut differences = vec![];
+    for first_wheel in &first {
+        let name = first_wheel.file_name().unwrap_or_default();
+        let Some(second_wheel) = second.iter().find(|wheel| wheel.file_name() == Some(name)) else {
+            differences.push(format!("{} was only built once", name.to_string_lossy()));
+            continue;
         };
-        wheel_paths.push(wheel_path);
+        if fs::read(first_wheel).await? == fs::read(second_wheel).await? {
+            println!("{} is reproducible", name.to_string_lossy());
+            continue;
+        }
+        for difference in wheels::diff_wheels(first_wheel, second_wheel).await? {
+            differences.push(format!("{}: {difference}", name.to_string_lossy()));
+        }
+    }
+    if second.len() > first.len() {
+        differences.push("The second build produced more wheels".to_owned());
     }
 
-    Ok(wheel_paths)
+    if !differences.is_empty() {
+        bail!(
+            "Builds of {} are not reproducible:\n{}",
+            recipe.name,
+            differences.join("\n")
+        );
+    }
+    Ok(())
 }
 
+/// Publish a release for the version with the wheels, their hashes and a manifest
 async fn publish_release(
-    project: SupportedProjects,
+    recipe: &Recipe,
     release_version: &str,
-    wheel_paths: &[PathBuf],
-    notes: &str,
-    replace_existing_release: bool,
+    output: &BuildOutput,
+    options: &PublishOptions,
 ) -> anyhow::Result<()> {
-    let tag = format!("{project}/v{release_version}");
+    let wheel_paths = &output.wheel_paths;
+    let hashes = generate_hashes(wheel_paths).await?;
+    let mut release = Release {
+        project: recipe.name.clone(),
+        version: release_version.to_owned(),
+        notes: options.notes.clone(),
+        replace_existing_release: options.replace_existing_release,
+        manifest: release_manifest(recipe, release_version, wheel_paths, &hashes).await?,
+        attachments: vec![],
+    };
 
-    // If the release already exists, delete first
-    if replace_existing_release
-        && run(Command::new("gh").args(["release", "view", &tag]))
-            .await
-            .is_ok()
-    {
-        run(Command::new("gh").args(["release", "delete", &tag, "--cleanup-tag"])).await?;
+    if let Some(signing_key) = &options.signing_key {
+        let dist_dir = wheel_paths
+            .first()
+            .and_then(|wheel_path| wheel_path.parent())
+            .context("No wheels to attest")?;
+        let provenance = BuildProvenance {
+            manifest: &release.manifest,
+            sdist_url: output.source.sdist_url.as_deref(),
+            sdist_sha256: output.source.sdist_sha256.as_deref(),
+            commands: &output.commands,
+            run_info: &options.notes,
+        }
+        .write(signing_key, dist_dir)
+        .await?;
+        release.attachments.push(provenance);
     }
 
-    let hashes = generate_hashes(wheel_paths).await?;
+    options
+        .target
+        .publisher()?
+        .publish(&release, wheel_paths, &hashes)
+        .await
+}
+
+/// Describe each wheel built for the version, and what it was built with
+async fn release_manifest(
+    recipe: &Recipe,
+    release_version: &str,
+    wheel_paths: &[PathBuf],
+    hashes: &str,
+) -> anyhow::Result<ReleaseManifest> {
+    let hashes = parse_hashes(hashes);
+
+    let mut wheels = vec![];
+    for wheel_path in wheel_paths {
+        let filename = wheel_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        // Pure Python wheels don't match any of the versions
+        let python_version = PythonVersion::iter().find(|&python_version| {
+            wheels::wheel_path(&recipe.name, python_version, "", release_version).file_name()
+                == wheel_path.file_name()
+        });
 
-    let notes = format!("{notes}\n\n### SHA256 Hashes\n\n```\n{hashes}```");
-
-    run(Command::new("gh").args(
-        [
-            "release", "create", &tag, "--title", &tag, "--notes", &notes,
-        ]
-        .into_iter()
-        .map(OsStr::new)
-        .chain(wheel_paths.iter().map(|p| p.as_os_str())),
-    ))
-    .await
+        wheels.push(WheelManifest {
+            sha256: hashes
+                .get(&filename)
+                .with_context(|| format!("Missing hash for {filename}"))?
+                .clone(),
+            size: fs::metadata(wheel_path).await?.len(),
+            python_version: python_version.map(|python_version| python_version.to_string()),
+            cpython_version: python_version
+                .map(|python_version| python_version.current_patch_version().to_owned()),
+            wasi_sdk_version: python_version
+                .map(|python_version| python_version.wasi_sdk_release_version().to_owned()),
+            wasi_wheels_version: env!("CARGO_PKG_VERSION").to_owned(),
+            build_flags: serde_json::to_value(cache::build_flags(wheel_path).await?)?,
+            filename,
+        });
+    }
+    wheels.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+    Ok(ReleaseManifest {
+        project: recipe.name.clone(),
+        version: release_version.to_owned(),
+        wheels,
+    })
 }
 
 async fn generate_hashes(wheel_paths: &[PathBuf]) -> anyhow::Result<String> {
@@ -145,15 +492,20 @@ async fn generate_hashes(wheel_paths: &[PathBuf]) -> anyhow::Result<String> {
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+
     use super::*;
 
     #[tokio::test]
     async fn test_project_display() -> anyhow::Result<()> {
+        let recipes = Recipes::load(None).await?;
         let wheel_paths = build(
-            SupportedProjects::PydanticCore,
-            "2.27.2",
-            None,
-            &[PythonVersion::Py3_12],
+            recipes.get("pydantic-core")?,
+            &Source::Release("2.27.2".to_owned()),
+            &BuildOptions {
+                python_versions: vec![PythonVersion::Py3_12],
+                ..BuildOptions::default()
+            },
         )
         .await?;
 
@@ -165,4 +517,58 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn describe_wheels_in_manifest() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let recipe = Recipe::parse("regex", r#"backend = "setuptools""#)?;
+        let wheel_paths = vec![
+            wheels::wheel_path("regex", PythonVersion::Py3_13, temp_dir.path(), "1.0"),
+            temp_dir.path().join("dist/regex-1.0-py3-none-any.whl"),
+        ];
+        fs::create_dir_all(temp_dir.path().join("dist")).await?;
+        let source = PreparedSource {
+            package_dir: temp_dir.path().to_owned(),
+            version: "1.0".to_owned(),
+            local_label: None,
+            sdist_sha256: None,
+            sdist_url: None,
+        };
+        let work_dir = temp_dir.path().join("regex-1.0-py3.13");
+        let inputs = BuildInputs::new(&recipe, &source, PythonVersion::Py3_13, &work_dir).await?;
+        for wheel_path in &wheel_paths {
+            fs::write(wheel_path, "wheel").await?;
+            inputs.write_manifest(wheel_path).await?;
+        }
+
+        let hashes = generate_hashes(&wheel_paths).await?;
+        let manifest = release_manifest(&recipe, "1.0", &wheel_paths, &hashes).await?;
+
+        let [wasi, pure_python] = manifest.wheels.as_slice() else {
+            panic!("Expected two wheels, got {:?}", manifest.wheels);
+        };
+        assert_eq!(wasi.filename, "regex-1.0-cp313-cp313-wasi_0_0_0_wasm32.whl");
+        assert_eq!(wasi.size, 5);
+        assert_eq!(wasi.python_version.as_deref(), Some("3.13"));
+        assert_eq!(
+            wasi.cpython_version.as_deref(),
+            Some(PythonVersion::Py3_13.current_patch_version())
+        );
+        // The flags the build command ran with, not the recipe
+        assert_eq!(wasi.build_flags["args"], json!(["-m", "build", "--wheel"]));
+        assert_eq!(
+            wasi.build_flags["env"]["CFLAGS"],
+            format!(
+                "-I{}/include/python3.13 -D__EMSCRIPTEN__=1 -fPIC -ffile-prefix-map={}=.",
+                PythonVersion::Py3_13.cross_prefix().display(),
+                work_dir.display()
+            )
+        );
+        assert_eq!(pure_python.build_flags, wasi.build_flags);
+        assert_eq!(manifest.hashes()[&wasi.filename], wasi.sha256);
+        assert_eq!(pure_python.python_version, None);
+        assert_eq!(pure_python.wasi_sdk_version, None);
+
+        Ok(())
+    }
 }
@@ -10,7 +10,10 @@ use strum::EnumIter;
 use tar::Archive;
 use tokio::{fs, process::Command};
 
-use crate::run;
+use crate::{
+    plan::{self, Action},
+    run,
+};
 
 use super::REPO_DIR;
 
@@ -34,7 +37,8 @@ impl std::fmt::Display for PythonVersion {
 
 impl PythonVersion {
     /// What the current, exact version being used is
-    fn current_patch_version(self) -> &'static str {
+    #[must_use]
+    pub fn current_patch_version(self) -> &'static str {
         match self {
             Self::Py3_12 => "3.12.9",
             Self::Py3_13 => "3.13.2",
@@ -48,6 +52,12 @@ impl PythonVersion {
         }
     }
 
+    /// Version of the WASI SDK used to compile for this python version
+    #[must_use]
+    pub fn wasi_sdk_release_version(self) -> &'static str {
+        self.wasi_sdk_version().version()
+    }
+
     /// Path to the WASI SDK directory that should be used for this python version
     #[must_use]
     pub fn wasi_sdk_path(self) -> PathBuf {
@@ -108,26 +118,30 @@ impl PythonVersion {
     /// # Panics
     /// If certain paths are invalid because of failed download
     async fn download_and_compile_with_wasi_script(self) -> anyhow::Result<()> {
-        #[cfg(any(target_os = "macos", target_os = "windows"))]
-        const PYTHON_EXECUTABLE: &str = "python.exe";
-        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-        const PYTHON_EXECUTABLE: &str = "python";
-        const HOST_TRIPLE: &str = "wasm32-wasip2";
         let version = self.current_patch_version();
         let cpython = self.cpython_dir();
 
-        if !cpython.exists() {
-            let bytes = get_bytes(format!(
-                "https://github.com/python/cpython/archive/refs/tags/v{version}.tar.gz",
-            ))
+        if !plan::exists(&cpython) {
+            download_and_unpack(
+                format!("https://github.com/python/cpython/archive/refs/tags/v{version}.tar.gz"),
+                &format!("cpython-{version}"),
+                &cpython,
+            )
             .await?;
-            tokio::task::spawn_blocking(move || {
-                Archive::new(GzDecoder::new(&bytes[..])).unpack(REPO_DIR.as_path())
-            })
-            .await??;
-            fs::rename(format!("cpython-{version}"), &cpython).await?;
         }
 
+        self.compile_with_wasi_script(&cpython).await
+    }
+
+    /// Compiles the `CPython` sources in the given directory with the wasi.py script. Steps that
+    /// a previous run already did are skipped.
+    async fn compile_with_wasi_script(self, cpython: &Path) -> anyhow::Result<()> {
+        #[cfg(any(target_os = "macos", target_os = "windows"))]
+        const PYTHON_EXECUTABLE: &str = "python.exe";
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        const PYTHON_EXECUTABLE: &str = "python";
+        const HOST_TRIPLE: &str = "wasm32-wasip2";
+
         let cpython_wasi_dir = cpython.join(format!("cross-build/{HOST_TRIPLE}"));
         let cpython_native_dir = cpython.join("cross-build/build");
         let wasi_sdk_path = self.wasi_sdk_path();
@@ -136,7 +150,7 @@ impl PythonVersion {
             if !cpython_native_dir.join(PYTHON_EXECUTABLE).exists() {
                 run(Command::new("python3")
                     .env("WASI_SDK_PATH", &wasi_sdk_path)
-                    .current_dir(&cpython)
+                    .current_dir(cpython)
                     .args([
                         "./Tools/wasm/wasi.py",
                         "configure-build-python",
@@ -148,14 +162,14 @@ impl PythonVersion {
 
                 run(Command::new("python3")
                     .env("WASI_SDK_PATH", &wasi_sdk_path)
-                    .current_dir(&cpython)
+                    .current_dir(cpython)
                     .args(["./Tools/wasm/wasi.py", "make-build-python", "--quiet"]))
                 .await?;
             }
 
             run(Command::new("python3")
                 .env("WASI_SDK_PATH", &wasi_sdk_path)
-                .current_dir(&cpython)
+                .current_dir(cpython)
                 .args([
                     "./Tools/wasm/wasi.py",
                     "configure-host",
@@ -174,7 +188,7 @@ impl PythonVersion {
 
             run(Command::new("python3")
                 .env("WASI_SDK_PATH", wasi_sdk_path)
-                .current_dir(&cpython)
+                .current_dir(cpython)
                 .args([
                     "./Tools/wasm/wasi.py",
                     "make-host",
@@ -200,16 +214,13 @@ impl PythonVersion {
         let version = self.current_patch_version();
         let cpython = self.cpython_dir();
 
-        if !cpython.exists() {
-            let bytes = get_bytes(format!(
-                "https://github.com/python/cpython/archive/refs/tags/v{version}.tar.gz",
-            ))
+        if !plan::exists(&cpython) {
+            download_and_unpack(
+                format!("https://github.com/python/cpython/archive/refs/tags/v{version}.tar.gz"),
+                &format!("cpython-{version}"),
+                &cpython,
+            )
             .await?;
-            tokio::task::spawn_blocking(move || {
-                Archive::new(GzDecoder::new(&bytes[..])).unpack(REPO_DIR.as_path())
-            })
-            .await??;
-            fs::rename(format!("cpython-{version}"), &cpython).await?;
         }
 
         let cpython_wasi_dir = cpython.join("builddir/wasi");
@@ -217,8 +228,8 @@ impl PythonVersion {
 
         if !cpython_wasi_dir.join(format!("libpython{self}.a")).exists() {
             if !cpython_native_dir.join(PYTHON_EXECUTABLE).exists() {
-                fs::create_dir_all(&cpython_native_dir).await?;
-                fs::create_dir_all(&cpython_wasi_dir).await?;
+                plan::create_dir_all(&cpython_native_dir).await?;
+                plan::create_dir_all(&cpython_wasi_dir).await?;
 
                 run(Command::new("../../configure")
                     .current_dir(&cpython_native_dir)
@@ -231,13 +242,18 @@ impl PythonVersion {
                 run(Command::new("make").current_dir(&cpython_native_dir)).await?;
             }
 
-            let config_guess = String::from_utf8(
-                Command::new("../../config.guess")
-                    .current_dir(&cpython_wasi_dir)
-                    .output()
-                    .await?
-                    .stdout,
-            )?;
+            // Nothing is unpacked to run it in when planning
+            let config_guess = if plan::is_planning() {
+                "$(../../config.guess)".to_owned()
+            } else {
+                String::from_utf8(
+                    Command::new("../../config.guess")
+                        .current_dir(&cpython_wasi_dir)
+                        .output()
+                        .await?
+                        .stdout,
+                )?
+            };
 
             run(Command::new("../../Tools/wasm/wasi-env")
                 .env("WASI_SDK_PATH", self.wasi_sdk_path())
@@ -306,6 +322,26 @@ async fn get_bytes(url: impl IntoUrl) -> anyhow::Result<bytes::Bytes> {
     Ok(bytes)
 }
 
+/// Download a gzipped tar archive, unpack it into the repository and move the unpacked
+/// directory to `dir`
+async fn download_and_unpack(url: String, unpacked: &str, dir: &Path) -> anyhow::Result<()> {
+    if plan::record(Action::Download {
+        url: url.clone(),
+        to: dir.to_owned(),
+    }) {
+        return Ok(());
+    }
+
+    let bytes = get_bytes(url).await?;
+    tokio::task::spawn_blocking(move || {
+        Archive::new(GzDecoder::new(&bytes[..])).unpack(REPO_DIR.as_path())
+    })
+    .await??;
+    fs::rename(unpacked, dir).await?;
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum WasiSdk {
     V24,
@@ -318,7 +354,7 @@ impl WasiSdk {
         }
     }
 
-    fn version(&self) -> &str {
+    fn version(self) -> &'static str {
         match self {
             Self::V24 => "24.0",
         }
@@ -334,7 +370,7 @@ impl WasiSdk {
     /// Will error if WASI SDK cannot be downloaded, or if called on an unsupported OS or Architecture.
     pub async fn download(self) -> anyhow::Result<()> {
         let dir = self.dir();
-        if !dir.exists() {
+        if !plan::exists(&dir) {
             let arch = match env::consts::ARCH {
                 arch @ "x86_64" => arch,
                 "aarch64" => "arm64",
@@ -345,19 +381,21 @@ impl WasiSdk {
             };
 
             let download_dir = format!("wasi-sdk-{}-{arch}-{os}", self.version());
-            let bytes = get_bytes(format!("https://github.com/WebAssembly/wasi-sdk/releases/download/{}/{download_dir}.tar.gz", self.release())).await?;
-
-            tokio::task::spawn_blocking(move || {
-                Archive::new(GzDecoder::new(&bytes[..])).unpack(REPO_DIR.as_path())
-            })
-            .await??;
-            fs::rename(download_dir, &dir).await?;
+            download_and_unpack(
+                format!(
+                    "https://github.com/WebAssembly/wasi-sdk/releases/download/{}/{download_dir}.tar.gz",
+                    self.release()
+                ),
+                &download_dir,
+                &dir,
+            )
+            .await?;
 
             // Hack for cpython to use wasip2 files. Uses wasip2 for wasi
             let sysroot_path = dir.join("share").join("wasi-sysroot");
             for dir in ["include", "lib", "share"] {
                 let dir = sysroot_path.join(dir);
-                fs::rename(dir.join("wasm32-wasi"), dir.join("wasm32-wasi-bk")).await?;
+                plan::rename(dir.join("wasm32-wasi"), dir.join("wasm32-wasi-bk")).await?;
                 run(Command::new("cp")
                     .args(["-r", "wasm32-wasip2", "wasm32-wasi"])
                     .current_dir(dir))
@@ -368,3 +406,98 @@ impl WasiSdk {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::runner::fake::{RecordedCommand, RecordingRunner};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn compile_cpython_with_wasi_script() -> anyhow::Result<()> {
+        let python_version = PythonVersion::Py3_13;
+        let temp_dir = tempfile::tempdir()?;
+        let cpython = temp_dir.path();
+        let wasi_dir = cpython.join("cross-build/wasm32-wasip2");
+
+        let runner = Arc::new(RecordingRunner::default());
+        runner
+            .record(python_version.compile_with_wasi_script(cpython))
+            .await?;
+
+        let commands = runner.commands();
+        assert_eq!(
+            commands
+                .iter()
+                .map(|command| (command.line(), command.current_dir.clone().unwrap()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "python3 ./Tools/wasm/wasi.py configure-build-python --quiet -- --config-cache"
+                        .to_owned(),
+                    cpython.to_owned()
+                ),
+                (
+                    "python3 ./Tools/wasm/wasi.py make-build-python --quiet".to_owned(),
+                    cpython.to_owned()
+                ),
+                (
+                    format!(
+                        "python3 ./Tools/wasm/wasi.py configure-host --host-triple=wasm32-wasip2 --host-runner=echo --quiet -- --config-cache --prefix={}/install --enable-ipv6 --disable-test-modules",
+                        wasi_dir.display()
+                    ),
+                    cpython.to_owned()
+                ),
+                (
+                    "python3 ./Tools/wasm/wasi.py make-host --quiet --host-triple=wasm32-wasip2"
+                        .to_owned(),
+                    cpython.to_owned()
+                ),
+                ("make install".to_owned(), wasi_dir.clone()),
+            ]
+        );
+        for command in &commands[..4] {
+            assert_eq!(
+                command.env["WASI_SDK_PATH"],
+                python_version.wasi_sdk_path().to_string_lossy()
+            );
+        }
+
+        // The build Python is reused
+        fs::create_dir_all(cpython.join("cross-build/build")).await?;
+        fs::write(cpython.join("cross-build/build/python"), "").await?;
+        let runner = Arc::new(RecordingRunner::default());
+        runner
+            .record(python_version.compile_with_wasi_script(cpython))
+            .await?;
+        assert_eq!(
+            runner
+                .commands()
+                .iter()
+                .map(RecordedCommand::line)
+                .collect::<Vec<_>>(),
+            [
+                format!(
+                    "python3 ./Tools/wasm/wasi.py configure-host --host-triple=wasm32-wasip2 --host-runner=echo --quiet -- --config-cache --prefix={}/install --enable-ipv6 --disable-test-modules",
+                    wasi_dir.display()
+                ),
+                "python3 ./Tools/wasm/wasi.py make-host --quiet --host-triple=wasm32-wasip2"
+                    .to_owned(),
+                "make install".to_owned(),
+            ]
+        );
+
+        // Nothing left to do once libpython is built
+        fs::create_dir_all(&wasi_dir).await?;
+        fs::write(wasi_dir.join("libpython3.13.a"), "").await?;
+        let runner = Arc::new(RecordingRunner::default());
+        runner
+            .record(python_version.compile_with_wasi_script(cpython))
+            .await?;
+        assert!(runner.commands().is_empty());
+
+        Ok(())
+    }
+}
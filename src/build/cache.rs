@@ -0,0 +1,311 @@
+//! Cache built wheels by a hash of everything that went into building them.
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{fs, process::Command};
+
+use crate::build::{
+    Backend, MaturinOptions, MesonOptions, PythonVersion, Recipe, flags::BuildFlags,
+    patches::APPLIED_PATCHES_FILE, source::PreparedSource,
+};
+
+/// Suffix of the manifest stored next to each wheel
+const MANIFEST_SUFFIX: &str = ".inputs.json";
+
+/// Version of the steps that process a wheel after the backend built it, i.e. retagging and
+/// normalizing. Bump it whenever they change, so wheels built before are rebuilt.
+const PIPELINE_VERSION: u32 = 2;
+
+/// Everything that affects the contents of a wheel
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildInputs {
+    /// Version of this tool
+    wasi_wheels_version: &'static str,
+    /// Version of the steps after the build command
+    pipeline_version: u32,
+    /// Sha256 of the sdist, if the source was unpacked from one
+    sdist_sha256: Option<String>,
+    /// Patches applied to the source, with their hashes
+    applied_patches: Option<String>,
+    /// Recipe settings that end up in the build commands, including the build requirement
+    /// specifiers
+    backend: Backend,
+    build_requirements: Vec<String>,
+    env: BTreeMap<String, String>,
+    rustflags: Vec<String>,
+    config_settings: Vec<String>,
+    maturin: MaturinOptions,
+    meson: MesonOptions,
+    /// Versions of tools used regardless of the Python version
+    tools: BTreeMap<&'static str, String>,
+    /// Python specific inputs. Left out for pure Python wheels, which are shared between versions.
+    python: Option<PythonInputs>,
+}
+
+/// Inputs that only matter for wheels with extension modules
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct PythonInputs {
+    /// Exact `CPython` version compiled for WASI
+    cpython_version: &'static str,
+    /// WASI SDK version used to compile extension modules
+    wasi_sdk_version: &'static str,
+    /// Version of the host Python used for the build environment
+    host_python: String,
+    /// Environment, arguments and generated files of the build command
+    flags: BuildFlags,
+}
+
+/// Stored next to each wheel, to tell if it needs to be rebuilt
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Manifest {
+    /// Hash of the inputs the wheel was built with
+    inputs_hash: String,
+    /// The inputs themselves, to make it possible to see what changed
+    inputs: serde_json::Value,
+    /// Flags of the command that built the wheel. Kept separately, since pure Python wheels
+    /// leave them out of their inputs.
+    #[serde(default)]
+    build_flags: Option<BuildFlags>,
+}
+
+impl BuildInputs {
+    /// Gather the inputs for building the source with the given Python version in the work
+    /// directory. Only reads the source, so it's cheap to check before copying it into the work
+    /// directory, and nothing is resolved over the network.
+    pub async fn new(
+        recipe: &Recipe,
+        source: &PreparedSource,
+        python_version: PythonVersion,
+        work_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        let applied_patches = fs::read_to_string(source.package_dir.join(APPLIED_PATCHES_FILE))
+            .await
+            .ok();
+
+        let mut tools = BTreeMap::new();
+        if recipe.backend == Backend::Maturin {
+            tools.insert("rustc", tool_version("rustc").await);
+        }
+
+        Ok(Self {
+            wasi_wheels_version: env!("CARGO_PKG_VERSION"),
+            pipeline_version: PIPELINE_VERSION,
+            sdist_sha256: source.sdist_sha256.clone(),
+            applied_patches,
+            backend: recipe.backend,
+            build_requirements: recipe.build_requirements.clone(),
+            env: recipe.env.clone(),
+            rustflags: recipe.rustflags.clone(),
+            config_settings: recipe.config_settings.clone(),
+            maturin: recipe.maturin.clone(),
+            meson: recipe.meson.clone(),
+            tools,
+            python: Some(PythonInputs {
+                cpython_version: python_version.current_patch_version(),
+                wasi_sdk_version: python_version.wasi_sdk_release_version(),
+                host_python: tool_version(&format!("python{python_version}")).await,
+                flags: BuildFlags::new(recipe, python_version, work_dir, &source.package_dir),
+            }),
+        })
+    }
+
+    /// The inputs that apply to the given wheel
+    fn for_wheel(&self, wheel: &Path) -> Self {
+        let mut inputs = self.clone();
+        if is_pure_python(wheel) {
+            inputs.python = None;
+        }
+        inputs
+    }
+
+    /// Hash of the inputs
+    fn hash(&self) -> anyhow::Result<String> {
+        Ok(format!("{:x}", Sha256::digest(serde_json::to_vec(self)?)))
+    }
+
+    /// Find a wheel among the candidates that was built with the same inputs
+    pub async fn cached_wheel(
+        &self,
+        candidates: impl IntoIterator<Item = PathBuf>,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        for wheel in candidates {
+            if !wheel.exists() {
+                continue;
+            }
+            let Ok(manifest) = fs::read(manifest_path(&wheel)).await else {
+                continue;
+            };
+            let Ok(manifest) = serde_json::from_slice::<Manifest>(&manifest) else {
+                continue;
+            };
+            if manifest.inputs_hash == self.for_wheel(&wheel).hash()? {
+                return Ok(Some(wheel));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Record the inputs a wheel was built with next to it
+    pub async fn write_manifest(&self, wheel: &Path) -> anyhow::Result<()> {
+        let inputs = self.for_wheel(wheel);
+        let manifest = Manifest {
+            inputs_hash: inputs.hash()?,
+            inputs: serde_json::to_value(&inputs)?,
+            build_flags: self.python.as_ref().map(|python| python.flags.clone()),
+        };
+        fs::write(
+            manifest_path(wheel),
+            serde_json::to_string_pretty(&manifest)?,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Flags of the command the wheel was built with, from the manifest next to it
+///
+/// # Errors
+/// If the wheel has no manifest with build flags.
+pub async fn build_flags(wheel: &Path) -> anyhow::Result<BuildFlags> {
+    let manifest = fs::read(manifest_path(wheel))
+        .await
+        .with_context(|| format!("Missing build manifest for {}", wheel.display()))?;
+    serde_json::from_slice::<Manifest>(&manifest)?
+        .build_flags
+        .with_context(|| format!("Missing build flags for {}", wheel.display()))
+}
+
+/// Path of the manifest for a given wheel
+fn manifest_path(wheel: &Path) -> PathBuf {
+    let mut path = wheel.as_os_str().to_owned();
+    path.push(MANIFEST_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Whether the wheel is tagged as pure Python, and so works for every Python version
+fn is_pure_python(wheel: &Path) -> bool {
+    wheel
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().ends_with("-none-any.whl"))
+}
+
+/// Output of `<tool> --version`, so upgrading a tool invalidates the cache
+async fn tool_version(tool: &str) -> String {
+    match Command::new(tool).arg("--version").output().await {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_owned()
+        }
+        _ => "unavailable".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn inputs(package_dir: &Path, sdist_sha256: &str) -> anyhow::Result<BuildInputs> {
+        inputs_with(package_dir, sdist_sha256, r#"backend = "setuptools""#).await
+    }
+
+    async fn inputs_with(
+        package_dir: &Path,
+        sdist_sha256: &str,
+        recipe: &str,
+    ) -> anyhow::Result<BuildInputs> {
+        let recipe = Recipe::parse("example", recipe)?;
+        let source = PreparedSource {
+            package_dir: package_dir.to_owned(),
+            version: "1.0.0".to_owned(),
+            local_label: None,
+            sdist_sha256: Some(sdist_sha256.to_owned()),
+            sdist_url: None,
+        };
+        BuildInputs::new(&recipe, &source, PythonVersion::Py3_12, package_dir).await
+    }
+
+    #[tokio::test]
+    async fn rebuild_when_inputs_change() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let wheel = temp_dir
+            .path()
+            .join("example-1.0.0-cp312-cp312-wasi_0_0_0_wasm32.whl");
+        fs::write(&wheel, "").await?;
+
+        let built = inputs(temp_dir.path(), "aaaa").await?;
+        // No manifest yet, i.e. built by an older version
+        assert!(built.cached_wheel([wheel.clone()]).await?.is_none());
+
+        built.write_manifest(&wheel).await?;
+        assert_eq!(
+            built.cached_wheel([wheel.clone()]).await?,
+            Some(wheel.clone())
+        );
+
+        let changed = inputs(temp_dir.path(), "bbbb").await?;
+        assert!(changed.cached_wheel([wheel.clone()]).await?.is_none());
+
+        // Different build requirements
+        let changed = inputs_with(
+            temp_dir.path(),
+            "aaaa",
+            "backend = \"setuptools\"\nbuild-requirements = [\"setuptools>=75\"]",
+        )
+        .await?;
+        assert!(changed.cached_wheel([wheel.clone()]).await?.is_none());
+
+        // Different flags in the build command
+        let changed = inputs_with(
+            temp_dir.path(),
+            "aaaa",
+            "backend = \"setuptools\"\nconfig-settings = [\"--fast\"]",
+        )
+        .await?;
+        assert!(changed.cached_wheel([wheel.clone()]).await?.is_none());
+        let mut changed = inputs(temp_dir.path(), "aaaa").await?;
+        if let Some(python) = &mut changed.python {
+            python
+                .flags
+                .env
+                .insert("CFLAGS".to_owned(), "-O0".to_owned());
+        }
+        assert!(changed.cached_wheel([wheel]).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pure_python_wheels_are_shared() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let wheel = temp_dir.path().join("example-1.0.0-py3-none-any.whl");
+        fs::write(&wheel, "").await?;
+
+        let mut built = inputs(temp_dir.path(), "aaaa").await?;
+        built.write_manifest(&wheel).await?;
+        // Still known for the release manifest
+        assert_eq!(
+            build_flags(&wheel).await?,
+            built.python.as_ref().unwrap().flags
+        );
+
+        // A different Python version can reuse the same wheel
+        built.python = Some(PythonInputs {
+            cpython_version: "3.13.2",
+            wasi_sdk_version: "24.0",
+            host_python: "Python 3.13.2".to_owned(),
+            flags: BuildFlags::default(),
+        });
+        assert_eq!(built.cached_wheel([wheel.clone()]).await?, Some(wheel));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+//! Remove toolchains, unpacked packages, virtual environments and wheels from previous builds.
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::build::{PACKAGES_DIR, PythonVersion, REPO_DIR};
+
+/// What kind of build output to delete
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, ValueEnum)]
+pub enum CleanScope {
+    /// Compiled `CPython` and WASI SDK directories
+    Toolchains,
+    /// Unpacked sdists and build directories, including their virtual environments and wheels
+    Packages,
+    /// Virtual environments inside of the unpacked packages
+    Venvs,
+    /// Built wheels inside of the unpacked packages
+    Wheels,
+}
+
+/// Delete the build output for the given scopes, or for all of them if none are given.
+///
+/// In gc mode, only toolchain directories that no supported Python version uses are deleted.
+/// With `dry_run`, only lists what would be deleted.
+///
+/// # Errors
+/// If the directories can't be read or deleted.
+pub async fn clean(
+    scopes: &[CleanScope],
+    gc: bool,
+    packages_dir: Option<PathBuf>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let packages_dir = packages_dir.unwrap_or_else(|| PACKAGES_DIR.clone());
+    let scopes = if scopes.is_empty() {
+        CleanScope::iter().collect()
+    } else {
+        scopes.to_vec()
+    };
+
+    let targets = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+        clean_targets(&scopes, gc, &REPO_DIR, &packages_dir)?
+            .into_iter()
+            .map(|path| Ok((dir_size(&path)?, path)))
+            .collect::<anyhow::Result<Vec<_>>>()
+    })
+    .await??;
+
+    if targets.is_empty() {
+        println!("Nothing to clean");
+        return Ok(());
+    }
+
+    println!(
+        "{}",
+        if dry_run {
+            "Would delete:"
+        } else {
+            "Deleting:"
+        }
+    );
+    let mut total = 0;
+    for (size, path) in &targets {
+        println!("{:>10}  {}", format_size(*size), path.display());
+        total += size;
+    }
+    println!("{:>10}  total", format_size(total));
+
+    if !dry_run {
+        for (_, path) in targets {
+            tokio::fs::remove_dir_all(path).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Directories to delete for the given scopes
+fn clean_targets(
+    scopes: &[CleanScope],
+    gc: bool,
+    repo_dir: &Path,
+    packages_dir: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut targets = vec![];
+
+    if gc || scopes.contains(&CleanScope::Toolchains) {
+        // Toolchains the supported Python versions still use
+        let in_use = PythonVersion::iter()
+            .flat_map(|python_version| {
+                [python_version.cpython_dir(), python_version.wasi_sdk_path()]
+            })
+            .filter_map(|path| path.file_name().map(ToOwned::to_owned))
+            .collect::<HashSet<_>>();
+
+        for path in subdirs(repo_dir)? {
+            let name = path.file_name().unwrap_or_default();
+            let dir_name = name.to_string_lossy();
+            let is_toolchain = dir_name.starts_with("cpython-") && dir_name.contains("-wasi-sdk-")
+                || dir_name.starts_with("wasi-sdk-");
+            if is_toolchain && !(gc && in_use.contains(name)) {
+                targets.push(path);
+            }
+        }
+    }
+    if gc {
+        return Ok(targets);
+    }
+
+    if scopes.contains(&CleanScope::Packages) {
+        targets.extend(subdirs(packages_dir)?);
+    } else {
+        for package_dir in subdirs(packages_dir)? {
+            for path in subdirs(&package_dir)? {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if scopes.contains(&CleanScope::Venvs) && name.starts_with(".venv-")
+                    || scopes.contains(&CleanScope::Wheels) && name == "dist"
+                {
+                    targets.push(path);
+                }
+            }
+        }
+    }
+
+    targets.sort();
+    Ok(targets)
+}
+
+/// Directories directly inside of the given one. Empty if it doesn't exist.
+fn subdirs(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut dirs = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Total size of the files in a directory, without following symlinks
+fn dir_size(path: &Path) -> anyhow::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+/// Format a size in bytes for humans
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    #[expect(clippy::cast_precision_loss, reason = "only for display")]
+    let mut size = size as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_dirs(root: &Path, dirs: &[&str]) -> anyhow::Result<()> {
+        for dir in dirs {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        Ok(())
+    }
+
+    fn names(targets: &[PathBuf], root: &Path) -> Vec<String> {
+        targets
+            .iter()
+            .map(|path| {
+                path.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gc_keeps_toolchains_in_use() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+        let current = PythonVersion::Py3_12;
+        create_dirs(
+            root,
+            &[
+                &current.cpython_dir().file_name().unwrap().to_string_lossy(),
+                &current
+                    .wasi_sdk_path()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy(),
+                "cpython-3.12.1-wasi-sdk-20.0",
+                "wasi-sdk-20.0",
+                "recipes",
+                "packages/regex-2024.11.6",
+            ],
+        )?;
+
+        let targets = clean_targets(&[], true, root, &root.join("packages"))?;
+
+        assert_eq!(
+            names(&targets, root),
+            ["cpython-3.12.1-wasi-sdk-20.0", "wasi-sdk-20.0"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn clean_venvs_and_wheels() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+        create_dirs(
+            root,
+            &[
+                "wasi-sdk-24.0",
+                "packages/regex-2024.11.6/.venv-3.12",
+                "packages/regex-2024.11.6/dist",
+                "packages/regex-2024.11.6/regex",
+                "packages/regex-2024.11.6-py3.13/.venv-3.13",
+            ],
+        )?;
+        let packages_dir = root.join("packages");
+
+        assert_eq!(
+            names(
+                &clean_targets(&[CleanScope::Venvs], false, root, &packages_dir)?,
+                root
+            ),
+            [
+                "packages/regex-2024.11.6/.venv-3.12",
+                "packages/regex-2024.11.6-py3.13/.venv-3.13",
+            ]
+        );
+        assert_eq!(
+            names(
+                &clean_targets(
+                    &[CleanScope::Packages, CleanScope::Wheels],
+                    false,
+                    root,
+                    &packages_dir
+                )?,
+                root
+            ),
+            [
+                "packages/regex-2024.11.6",
+                "packages/regex-2024.11.6-py3.13"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(format_size(12), "12 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -0,0 +1,164 @@
+//! The settings a backend's build command actually runs with, so they can be part of the cache
+//! key and the release manifest.
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::build::{
+    Backend, PythonVersion, Recipe, maturin, meson, scikit_build,
+    setuptools::{self, Compilers},
+    source::source_date_epoch,
+};
+
+/// Environment variables, arguments and generated files of a backend's build command
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildFlags {
+    /// Arguments of the build command
+    pub args: Vec<String>,
+    /// Environment variables set for the build command. `PATH` is left out, since it depends on
+    /// the machine rather than the build.
+    pub env: BTreeMap<String, String>,
+    /// Generated cross or toolchain files the compilers are set in, by file name
+    pub files: BTreeMap<String, String>,
+}
+
+impl BuildFlags {
+    /// The flags the recipe's backend builds the package directory with, once the source tree
+    /// is copied into it. The package directory doesn't need to exist yet.
+    pub fn new(
+        recipe: &Recipe,
+        python_version: PythonVersion,
+        package_dir: &Path,
+        source_dir: &Path,
+    ) -> Self {
+        let mut flags = Self::for_command(recipe, python_version, package_dir);
+        // Read from the copy when building, which may not be there yet
+        flags.env.remove("SOURCE_DATE_EPOCH");
+        if let Some(epoch) = source_date_epoch(source_dir) {
+            flags
+                .env
+                .insert("SOURCE_DATE_EPOCH".to_owned(), epoch.to_string());
+        }
+        flags
+    }
+
+    /// Flags of the backend's build command for the package directory
+    fn for_command(recipe: &Recipe, python_version: PythonVersion, package_dir: &Path) -> Self {
+        let compilers = match recipe.backend {
+            Backend::Maturin => {
+                return Self::from_command(
+                    &maturin::command(recipe, python_version, package_dir, ""),
+                    &Compilers::Environment,
+                );
+            }
+            Backend::Setuptools => Compilers::Environment,
+            Backend::MesonPython => meson::compilers(recipe, python_version, package_dir),
+            Backend::ScikitBuildCore => scikit_build::compilers(python_version, package_dir),
+        };
+        Self::from_command(
+            &setuptools::command(recipe, python_version, package_dir, "", &compilers),
+            &compilers,
+        )
+    }
+
+    /// Flags of the command, and the file it reads its compilers from
+    fn from_command(command: &Command, compilers: &Compilers) -> Self {
+        let command = command.as_std();
+        Self {
+            args: command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            env: command
+                .get_envs()
+                .filter(|(key, _)| *key != "PATH")
+                .filter_map(|(key, value)| {
+                    Some((
+                        key.to_string_lossy().into_owned(),
+                        value?.to_string_lossy().into_owned(),
+                    ))
+                })
+                .collect(),
+            files: compilers
+                .file()
+                .into_iter()
+                .map(|file| {
+                    (
+                        file.path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .into_owned(),
+                        file.contents.clone(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::SOURCE_DATE_EPOCH_FILE;
+
+    #[test]
+    fn flags_of_build_command() -> anyhow::Result<()> {
+        let package_dir = Path::new("/packages/example-1.0-py3.12");
+        let python_version = PythonVersion::Py3_12;
+
+        let recipe = Recipe::parse(
+            "example",
+            r#"
+            backend = "setuptools"
+            config-settings = ["--build-option=--fast"]
+
+            [env]
+            EXAMPLE = "1"
+            "#,
+        )?;
+        let flags = BuildFlags::new(&recipe, python_version, package_dir, package_dir);
+        assert_eq!(
+            flags.args,
+            ["-m", "build", "--wheel", "-C--build-option=--fast"]
+        );
+        assert_eq!(flags.env["EXAMPLE"], "1");
+        assert!(flags.env["CFLAGS"].ends_with("-ffile-prefix-map=/packages/example-1.0-py3.12=."));
+        assert!(!flags.env.contains_key("PATH"));
+        assert!(flags.files.is_empty());
+
+        let recipe = Recipe::parse("example", r#"backend = "meson-python""#)?;
+        let flags = BuildFlags::new(&recipe, python_version, package_dir, package_dir);
+        assert!(!flags.env.contains_key("CFLAGS"));
+        assert!(
+            flags.files["wasi-cross-3.12.ini"]
+                .contains("'-ffile-prefix-map=/packages/example-1.0-py3.12=.'")
+        );
+
+        let recipe = Recipe::parse("example", r#"backend = "maturin""#)?;
+        let flags = BuildFlags::new(&recipe, python_version, package_dir, package_dir);
+        assert!(flags.env["RUSTFLAGS"].contains("--remap-path-prefix="));
+
+        Ok(())
+    }
+
+    #[test]
+    fn flags_before_copying_source() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path().join("example-1.0");
+        std::fs::create_dir_all(&source_dir)?;
+        std::fs::write(source_dir.join(SOURCE_DATE_EPOCH_FILE), "1700000000\n")?;
+        let recipe = Recipe::parse("example", r#"backend = "setuptools""#)?;
+
+        let flags = BuildFlags::new(
+            &recipe,
+            PythonVersion::Py3_12,
+            &temp_dir.path().join("example-1.0-py3.12"),
+            &source_dir,
+        );
+        assert_eq!(flags.env["SOURCE_DATE_EPOCH"], "1700000000");
+
+        Ok(())
+    }
+}
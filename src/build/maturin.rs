@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+
+use crate::{
+    build::{
+        build_tools::PythonVersion,
+        recipe::Recipe,
+        wheels::{default_wheel_flags, retag_wheel, wheel_path},
+    },
+    run,
+    runner::{RunOptions, run_with},
+};
+
+/// Rust target that `PyO3` extensions are compiled for
+const RUST_TARGET: &str = "wasm32-wasip1";
+
+/// Builds a maturin based project and returns the wheel path for publishing
+pub async fn build(
+    recipe: &Recipe,
+    python_version: PythonVersion,
+    package_dir: &Path,
+    version: &str,
+) -> anyhow::Result<PathBuf> {
+    let path_variable = python_version.create_venv(package_dir).await?;
+
+    let wheel = wheel_path(&recipe.name, python_version, package_dir, version);
+    run_with(
+        Command::new("pip")
+            .arg("install")
+            .args(&recipe.build_requirements)
+            .arg("--upgrade")
+            // Make it possible to not have to activate the venv
+            .env("PATH", &path_variable),
+        &RunOptions::network(),
+    )
+    .await?;
+
+    run(&mut command(
+        recipe,
+        python_version,
+        package_dir,
+        &path_variable,
+    ))
+    .await?;
+
+    // Rewrite the wheel to the correct target
+    retag_wheel(
+        &recipe.name,
+        python_version,
+        package_dir,
+        version,
+        &path_variable,
+    )
+    .await?;
+
+    Ok(wheel)
+}
+
+/// The `maturin build` invocation with the flags to cross compile for WASI
+pub fn command(
+    recipe: &Recipe,
+    python_version: PythonVersion,
+    package_dir: &Path,
+    path_variable: &str,
+) -> Command {
+    let mut command = maturin_command(recipe, python_version);
+    default_wheel_flags(&mut command, python_version, package_dir, path_variable)
+        .env("PYO3_CROSS_LIB_DIR", python_version.cross_lib_dir())
+        .env("RUSTFLAGS", rustflags(recipe, python_version, package_dir))
+        .env("CARGO_BUILD_TARGET", RUST_TARGET)
+        .envs(&recipe.env);
+    command
+}
+
+/// The `maturin build` arguments, including any per-project overrides
+fn maturin_command(recipe: &Recipe, python_version: PythonVersion) -> Command {
+    let mut command = Command::new("maturin");
+    command.args([
+        "build",
+        "--release",
+        "--target",
+        RUST_TARGET,
+        "--out",
+        "dist",
+        "-i",
+        &format!("python{python_version}"),
+        "--strip",
+    ]);
+    if let Some(manifest_path) = &recipe.maturin.manifest_path {
+        command.arg("--manifest-path").arg(manifest_path);
+    }
+    if !recipe.maturin.features.is_empty() {
+        command.args(["--features", &recipe.maturin.features.join(",")]);
+    }
+    command
+}
+
+/// Flags needed to link a shared library against the WASI SDK, plus any extra project flags.
+/// The package directory is remapped to `.` so it doesn't end up in the built wheel.
+fn rustflags(recipe: &Recipe, python_version: PythonVersion, package_dir: &Path) -> String {
+    let wasi_sdk_path = python_version.wasi_sdk_path();
+
+    [format!(
+        "-C link-args=-L{wasi_sdk}/share/wasi-sysroot/lib/{RUST_TARGET}/ -C link-self-contained=no -C link-args=--experimental-pic -C link-args=--shared -C relocation-model=pic -C linker-plugin-lto=yes -C opt-level=s -C lto=true -C codegen-units=1 --remap-path-prefix={package_dir}=.",
+        wasi_sdk = wasi_sdk_path.to_str().unwrap(),
+        package_dir = package_dir.display()
+    )]
+    .into_iter()
+    .chain(recipe.rustflags.iter().cloned())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        build::Recipes,
+        runner::fake::{RecordedCommand, RecordingRunner},
+    };
+
+    use super::*;
+
+    #[test]
+    fn maturin_overrides() -> anyhow::Result<()> {
+        let recipe = Recipe::parse(
+            "example",
+            r#"
+            backend = "maturin"
+            rustflags = ["-C target-feature=+simd128"]
+
+            [maturin]
+            features = ["a", "b"]
+            manifest-path = "bindings/python/Cargo.toml"
+            "#,
+        )?;
+
+        let command = maturin_command(&recipe, PythonVersion::Py3_12);
+        let args = command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>();
+        assert!(
+            args.windows(2)
+                .any(|w| w == ["--manifest-path", "bindings/python/Cargo.toml"])
+        );
+        assert!(args.windows(2).any(|w| w == ["--features", "a,b"]));
+
+        let rustflags = rustflags(
+            &recipe,
+            PythonVersion::Py3_12,
+            Path::new("/packages/example"),
+        );
+        assert!(rustflags.contains(" --remap-path-prefix=/packages/example=. "));
+        assert!(rustflags.ends_with(" -C target-feature=+simd128"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn build_pydantic_core() -> anyhow::Result<()> {
+        let recipes = Recipes::load(None).await?;
+        let recipe = recipes.get("pydantic-core")?;
+        let temp_dir = tempfile::tempdir()?;
+        let package_dir = temp_dir.path();
+        let runner = Arc::new(RecordingRunner::building(
+            "pydantic_core-2.27.2-cp312-cp312-any.whl",
+        ));
+
+        let wheel = runner
+            .record(build(recipe, PythonVersion::Py3_12, package_dir, "2.27.2"))
+            .await?;
+
+        assert_eq!(
+            wheel,
+            package_dir.join("dist/pydantic_core-2.27.2-cp312-cp312-wasi_0_0_0_wasm32.whl")
+        );
+        let commands = runner.commands();
+        assert_eq!(
+            commands
+                .iter()
+                .map(RecordedCommand::line)
+                .collect::<Vec<_>>(),
+            [
+                format!(
+                    "python3.12 -m venv {}",
+                    package_dir.join(".venv-3.12").display()
+                ),
+                "pip install typing-extensions maturin --upgrade".to_owned(),
+                "maturin build --release --target wasm32-wasip1 --out dist -i python3.12 --strip"
+                    .to_owned(),
+                "pip install wheel --upgrade".to_owned(),
+                format!(
+                    "wheel tags --platform-tag wasi_0_0_0_wasm32 --remove {}",
+                    package_dir
+                        .join("dist/pydantic_core-2.27.2-cp312-cp312-any.whl")
+                        .display()
+                ),
+            ]
+        );
+
+        let maturin = &commands[2];
+        assert_eq!(maturin.current_dir.as_deref(), Some(package_dir));
+        assert_eq!(maturin.env["CARGO_BUILD_TARGET"], RUST_TARGET);
+        assert_eq!(
+            maturin.env["PYO3_CROSS_LIB_DIR"],
+            PythonVersion::Py3_12.cross_lib_dir().to_string_lossy()
+        );
+        assert_eq!(
+            maturin.env["RUSTFLAGS"],
+            rustflags(recipe, PythonVersion::Py3_12, package_dir)
+        );
+        assert!(
+            maturin.env["PATH"].starts_with(
+                &package_dir
+                    .join(".venv-3.12/bin")
+                    .to_string_lossy()
+                    .into_owned()
+            )
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::build::{
+    build_tools::PythonVersion,
+    recipe::Recipe,
+    setuptools::{self, Compilers, ConfigFile},
+    wheels::prefix_map,
+};
+
+/// Builds a meson-python project and returns the wheel path for publishing. The compilers only
+/// come from the generated cross file.
+pub async fn build(
+    recipe: &Recipe,
+    python_version: PythonVersion,
+    package_dir: &Path,
+    version: &str,
+) -> anyhow::Result<PathBuf> {
+    setuptools::build(
+        recipe,
+        python_version,
+        package_dir,
+        version,
+        &compilers(recipe, python_version, package_dir),
+    )
+    .await
+}
+
+/// The generated cross file, and the config setting pointing meson at it
+pub fn compilers(recipe: &Recipe, python_version: PythonVersion, package_dir: &Path) -> Compilers {
+    let path = package_dir.join(format!("wasi-cross-{python_version}.ini"));
+    Compilers::CrossFile(ConfigFile {
+        setting: format!("setup-args=--cross-file={}", path.to_string_lossy()),
+        contents: cross_file(recipe, python_version, package_dir),
+        path,
+    })
+}
+
+/// Generate a meson cross file targeting WASI with the given Python version. The package
+/// directory is mapped to `.` in the compiled extensions.
+fn cross_file(recipe: &Recipe, python_version: PythonVersion, package_dir: &Path) -> String {
+    let wasi_sdk_path = python_version.wasi_sdk_path();
+    let python_include = python_version
+        .cross_prefix()
+        .join(format!("include/python{python_version}"));
+    let c_args = [
+        format!("-I{}", python_include.to_string_lossy()),
+        "-D__EMSCRIPTEN__=1".to_owned(),
+        "-fPIC".to_owned(),
+        prefix_map(package_dir),
+    ];
+
+    let mut file = String::new();
+
+    writeln!(file, "[binaries]").unwrap();
+    for (name, binary) in [("c", "clang"), ("cpp", "clang++"), ("ar", "ar")] {
+        let path = wasi_sdk_path.join("bin").join(binary);
+        writeln!(file, "{name} = {}", quote(&path.to_string_lossy())).unwrap();
+    }
+
+    writeln!(file, "\n[built-in options]").unwrap();
+    writeln!(file, "c_args = {}", array(&c_args)).unwrap();
+    writeln!(file, "cpp_args = {}", array(&c_args)).unwrap();
+
+    writeln!(file, "\n[properties]").unwrap();
+    writeln!(file, "needs_exe_wrapper = true").unwrap();
+    writeln!(
+        file,
+        "sys_root = {}",
+        quote(&wasi_sdk_path.join("share/wasi-sysroot").to_string_lossy())
+    )
+    .unwrap();
+    for (key, value) in &recipe.meson.properties {
+        writeln!(file, "{key} = {}", quote(value)).unwrap();
+    }
+
+    writeln!(file, "\n[host_machine]").unwrap();
+    writeln!(file, "system = 'wasi'").unwrap();
+    writeln!(file, "cpu_family = 'wasm32'").unwrap();
+    writeln!(file, "cpu = 'wasm32'").unwrap();
+    writeln!(file, "endian = 'little'").unwrap();
+
+    file
+}
+
+/// Quote a value as a meson string
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Format values as a meson array of strings
+fn array(values: &[String]) -> String {
+    format!(
+        "[{}]",
+        values
+            .iter()
+            .map(|value| quote(value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::runner::fake::RecordingRunner;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn compilers_only_come_from_cross_file() -> anyhow::Result<()> {
+        let recipe = Recipe::parse("example", r#"backend = "meson-python""#)?;
+        let temp_dir = tempfile::tempdir()?;
+        let package_dir = temp_dir.path();
+        let runner = Arc::new(RecordingRunner::building("example-1.0-py3-none-any.whl"));
+
+        runner
+            .record(build(&recipe, PythonVersion::Py3_12, package_dir, "1.0"))
+            .await?;
+
+        let commands = runner.commands();
+        let build = commands
+            .iter()
+            .find(|command| command.program == "python")
+            .unwrap();
+        assert!(build.args.contains(&format!(
+            "-Csetup-args=--cross-file={}",
+            package_dir.join("wasi-cross-3.12.ini").display()
+        )));
+        for key in [
+            "CC", "CXX", "CFLAGS", "CXXFLAGS", "LDFLAGS", "LDSHARED", "AR",
+        ] {
+            assert!(!build.env.contains_key(key), "{key}");
+        }
+        assert!(build.env.contains_key("_PYTHON_SYSCONFIGDATA_NAME"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_cross_file() -> anyhow::Result<()> {
+        let recipe = Recipe::parse(
+            "numpy",
+            r#"
+            backend = "meson-python"
+
+            [meson.properties]
+            longdouble_format = "IEEE_QUAD_LE"
+            "#,
+        )?;
+        let python_version = PythonVersion::Py3_12;
+        let file = cross_file(&recipe, python_version, Path::new("/packages/numpy"));
+        let wasi_sdk = python_version.wasi_sdk_path();
+
+        assert!(file.contains(&format!(
+            "c = '{}'",
+            wasi_sdk.join("bin/clang").to_string_lossy()
+        )));
+        assert!(file.contains(&format!(
+            "-I{}/include/python3.12",
+            python_version.cross_prefix().to_string_lossy()
+        )));
+        assert!(file.contains("'-ffile-prefix-map=/packages/numpy=.'"));
+        assert!(file.contains("longdouble_format = 'IEEE_QUAD_LE'"));
+        assert!(file.contains("[host_machine]\nsystem = 'wasi'\ncpu_family = 'wasm32'"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn quote_meson_strings() {
+        assert_eq!(quote(r"it's a\path"), r"'it\'s a\\path'");
+    }
+}
@@ -0,0 +1,192 @@
+//! Apply source patches from a recipe to an unpacked sdist before building.
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, bail};
+use pep440_rs::Version;
+use sha2::{Digest, Sha256};
+use tokio::{fs, process::Command};
+
+use crate::{build::recipe::Recipe, plan, run};
+
+/// File in the package directory tracking which patches have already been applied
+pub const APPLIED_PATCHES_FILE: &str = ".wasi-wheels-patches";
+
+/// Apply every patch in the recipe that matches the version to the package directory.
+///
+/// Patches that were already applied are skipped. Errors if a patch does not apply cleanly, or
+/// if an already applied patch has since changed.
+pub async fn apply_patches(
+    recipe: &Recipe,
+    package_dir: impl Into<PathBuf>,
+    version: &str,
+) -> anyhow::Result<()> {
+    let package_dir = package_dir.into();
+    let parsed_version = Version::from_str(version)
+        .with_context(|| format!("Invalid version {version} for {}", recipe.name))?;
+    let patches = recipe
+        .patches
+        .iter()
+        .filter(|patch| {
+            patch
+                .versions
+                .as_ref()
+                .is_none_or(|versions| versions.contains(&parsed_version))
+        })
+        .collect::<Vec<_>>();
+    if patches.is_empty() {
+        return Ok(());
+    }
+
+    let applied_file = package_dir.join(APPLIED_PATCHES_FILE);
+    let mut applied = if applied_file.exists() {
+        fs::read_to_string(&applied_file).await?
+    } else {
+        String::new()
+    };
+    // Patch name -> hash of the patch contents when it was applied
+    let applied_hashes = applied
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(hash, name)| (name.to_owned(), hash.to_owned()))
+        .collect::<HashMap<_, _>>();
+
+    for patch in patches {
+        let name = patch.file.file_name().unwrap_or_default().to_string_lossy();
+        let contents = fs::read(&patch.file)
+            .await
+            .with_context(|| format!("Unable to read patch {}", patch.file.display()))?;
+        let hash = format!("{:x}", Sha256::digest(&contents));
+
+        match applied_hashes.get(name.as_ref()) {
+            Some(applied_hash) if *applied_hash == hash => continue,
+            Some(_) => bail!(
+                "Patch {name} changed since it was applied to {}. Remove the directory to start from a clean sdist.",
+                package_dir.display()
+            ),
+            None => {}
+        }
+
+        // Check first, so a failing patch doesn't leave the package half patched
+        run(patch_command(&patch.file, &package_dir).arg("--dry-run"))
+            .await
+            .with_context(|| {
+                format!(
+                    "Patch {name} no longer applies to {} {version}",
+                    recipe.name
+                )
+            })?;
+        run(&mut patch_command(&patch.file, &package_dir)).await?;
+
+        writeln!(applied, "{hash}\t{name}").unwrap();
+        plan::write(&applied_file, &applied).await?;
+    }
+
+    Ok(())
+}
+
+/// Command to apply the given patch file within the package directory
+fn patch_command(patch_file: &Path, package_dir: &Path) -> Command {
+    let mut command = Command::new("patch");
+    command
+        .args(["-p1", "--forward", "--batch", "--input"])
+        .arg(patch_file)
+        .current_dir(package_dir);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build::recipe::Patch;
+
+    use super::*;
+
+    const PATCH: &str = "\
+--- a/module.c
++++ b/module.c
+@@ -1 +1 @@
+-#define USE_THREADS 1
++#define USE_THREADS 0
+";
+
+    fn recipe(patch_file: PathBuf, versions: &str) -> anyhow::Result<Recipe> {
+        let mut recipe = Recipe::parse("example", "backend = \"setuptools\"")?;
+        recipe.patches.push(Patch {
+            file: patch_file,
+            versions: Some(versions.parse()?),
+        });
+        Ok(recipe)
+    }
+
+    #[tokio::test]
+    async fn applies_patches_once() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let package_dir = temp_dir.path().join("example-1.0");
+        fs::create_dir(&package_dir).await?;
+        fs::write(package_dir.join("module.c"), "#define USE_THREADS 1\n").await?;
+        let patch_file = temp_dir.path().join("no-threads.patch");
+        fs::write(&patch_file, PATCH).await?;
+
+        let recipe = recipe(patch_file, ">=1.0")?;
+        apply_patches(&recipe, &package_dir, "1.0").await?;
+        // Second time is a no-op, instead of failing to apply again
+        apply_patches(&recipe, &package_dir, "1.0").await?;
+
+        assert_eq!(
+            fs::read_to_string(package_dir.join("module.c")).await?,
+            "#define USE_THREADS 0\n"
+        );
+        assert_eq!(
+            fs::read_to_string(package_dir.join(APPLIED_PATCHES_FILE))
+                .await?
+                .lines()
+                .count(),
+            1
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn skips_patches_for_other_versions() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let recipe = recipe(temp_dir.path().join("missing.patch"), "<1.0")?;
+
+        apply_patches(&recipe, temp_dir.path(), "1.0").await?;
+
+        assert!(!temp_dir.path().join(APPLIED_PATCHES_FILE).exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fails_when_patch_does_not_apply() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let package_dir = temp_dir.path().join("example-1.0");
+        fs::create_dir(&package_dir).await?;
+        fs::write(package_dir.join("module.c"), "// Something else\n").await?;
+        let patch_file = temp_dir.path().join("no-threads.patch");
+        fs::write(&patch_file, PATCH).await?;
+
+        let recipe = recipe(patch_file, ">=1.0")?;
+        let error = apply_patches(&recipe, &package_dir, "1.0")
+            .await
+            .unwrap_err();
+
+        assert!(
+            error
+                .to_string()
+                .contains("Patch no-threads.patch no longer applies to example 1.0")
+        );
+        assert_eq!(
+            fs::read_to_string(package_dir.join("module.c")).await?,
+            "// Something else\n"
+        );
+
+        Ok(())
+    }
+}
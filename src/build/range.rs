@@ -0,0 +1,216 @@
+//! Build every version of a project that hasn't been published yet.
+use std::{collections::HashSet, fmt::Write};
+
+use anyhow::bail;
+use pep440_rs::Version;
+
+use crate::{
+    PublishOptions, PublishTarget,
+    build::{BuildOptions, Recipe, Source, build_and_publish},
+    released_versions,
+};
+
+/// Outcome of building a single version in a range
+#[derive(Debug)]
+enum Outcome {
+    /// Wheels were built
+    Built,
+    /// Wheels were built and published as a release
+    Published,
+    /// The build or publish failed with the given error
+    Failed(String),
+    /// Not attempted, because an earlier build failed
+    Skipped,
+}
+
+/// Build (and optionally publish) every released version of a project since the given version
+/// that doesn't have a release in the target yet, oldest first. Prints a summary table at the
+/// end.
+///
+/// Unless `keep_going` is set, stops at the first failure.
+///
+/// # Errors
+/// If the versions can't be retrieved, or any of the builds fail.
+pub async fn build_range(
+    recipe: &Recipe,
+    since: &str,
+    target: &PublishTarget,
+    options: &BuildOptions,
+    publish: Option<PublishOptions>,
+    keep_going: bool,
+) -> anyhow::Result<()> {
+    let since = since.parse::<Version>()?;
+
+    let versions = missing_versions(
+        recipe,
+        &since,
+        released_versions(&recipe.name).await?,
+        &target.publisher()?.published_versions(&recipe.name).await?,
+    );
+    if versions.is_empty() {
+        println!(
+            "All versions of {} since {since} are published",
+            recipe.name
+        );
+        return Ok(());
+    }
+    println!(
+        "Building {} version(s) of {}: {}",
+        versions.len(),
+        recipe.name,
+        versions.join(", ")
+    );
+
+    let mut outcomes = vec![];
+    let mut failed = false;
+    for version in versions {
+        if failed && !keep_going {
+            outcomes.push((version, Outcome::Skipped));
+            continue;
+        }
+
+        let outcome = match build_and_publish(
+            recipe,
+            &Source::Release(version.clone()),
+            options,
+            publish.as_ref(),
+        )
+        .await
+        {
+            Ok(()) if publish.is_some() => Outcome::Published,
+            Ok(()) => Outcome::Built,
+            Err(error) => {
+                eprintln!("Failed to build {} {version}: {error:#}", recipe.name);
+                failed = true;
+                Outcome::Failed(format!("{error:#}"))
+            }
+        };
+        outcomes.push((version, outcome));
+    }
+
+    print!("{}", summary(&outcomes));
+
+    let failures = outcomes
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+        .count();
+    if failures > 0 {
+        bail!(
+            "{failures} of {} build(s) of {} failed",
+            outcomes.len(),
+            recipe.name
+        );
+    }
+
+    Ok(())
+}
+
+/// Versions that are at least `since`, supported by the recipe and not published yet, sorted
+/// oldest first. Pre-releases are skipped.
+fn missing_versions(
+    recipe: &Recipe,
+    since: &Version,
+    released: impl IntoIterator<Item = String>,
+    published: &HashSet<String>,
+) -> Vec<String> {
+    let published = published
+        .iter()
+        .filter_map(|version| version.parse::<Version>().ok())
+        .collect::<HashSet<_>>();
+
+    let mut versions = released
+        .into_iter()
+        .filter_map(|version| Some((version.parse::<Version>().ok()?, version)))
+        .filter(|(parsed, _)| {
+            parsed >= since
+                && !parsed.any_prerelease()
+                && recipe.supports(parsed)
+                && !published.contains(parsed)
+        })
+        .collect::<Vec<_>>();
+    versions.sort();
+
+    versions.into_iter().map(|(_, version)| version).collect()
+}
+
+/// Render a table of the outcome of each version
+fn summary(outcomes: &[(String, Outcome)]) -> String {
+    let width = outcomes
+        .iter()
+        .map(|(version, _)| version.len())
+        .chain(["Version".len()])
+        .max()
+        .unwrap_or_default();
+
+    let mut table = String::new();
+    writeln!(table, "{:width$}  Result", "Version").unwrap();
+    for (version, outcome) in outcomes {
+        let result = match outcome {
+            Outcome::Built => "built".to_owned(),
+            Outcome::Published => "published".to_owned(),
+            Outcome::Failed(error) => {
+                format!("failed: {}", error.lines().next().unwrap_or_default())
+            }
+            Outcome::Skipped => "skipped".to_owned(),
+        };
+        writeln!(table, "{version:width$}  {result}").unwrap();
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_missing_versions() -> anyhow::Result<()> {
+        let recipe = Recipe::parse(
+            "regex",
+            r#"
+            backend = "setuptools"
+            versions = ">=2021.11.10"
+            "#,
+        )?;
+        let released = [
+            "2021.8.3",
+            "2024.11.6",
+            "2022.1.18",
+            "2023.12.25",
+            "2025.1.0rc1",
+            "2022.10.31",
+            "not-a-version",
+        ]
+        .map(str::to_owned);
+        let published = HashSet::from(["2022.10.31".to_owned()]);
+
+        assert_eq!(
+            missing_versions(&recipe, &"2022.1.0".parse()?, released, &published),
+            ["2022.1.18", "2023.12.25", "2024.11.6"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_summary() {
+        let table = summary(&[
+            ("2.27.1".to_owned(), Outcome::Published),
+            (
+                "2.27.2".to_owned(),
+                Outcome::Failed("pip failed\ncaused by".to_owned()),
+            ),
+            ("2.28.0".to_owned(), Outcome::Skipped),
+            ("2.28.1".to_owned(), Outcome::Built),
+        ]);
+
+        assert_eq!(
+            table,
+            "Version  Result\n\
+             2.27.1   published\n\
+             2.27.2   failed: pip failed\n\
+             2.28.0   skipped\n\
+             2.28.1   built\n"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Declarative build recipes, loaded from TOML files in a recipes directory.
+//!
+//! Each `<project>.toml` file describes how to build a single project, so new
+//! packages can be supported without any changes to this crate.
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use pep440_rs::{Version, VersionSpecifiers};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::REPO_DIR;
+
+/// Directory containing the recipes that ship with this repository
+pub static RECIPES_DIR: LazyLock<PathBuf> = LazyLock::new(|| REPO_DIR.join("recipes"));
+
+/// Build backend used to turn an sdist into a wheel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Backend {
+    /// `python -m build --wheel` with cross-compilation flags (setuptools and friends)
+    Setuptools,
+    /// `maturin build` for Rust based `PyO3` extensions
+    Maturin,
+    /// `python -m build --wheel` for meson-python projects, with a generated meson cross file
+    MesonPython,
+    /// `python -m build --wheel` for scikit-build-core projects, with a generated `CMake` toolchain
+    /// file
+    ScikitBuildCore,
+}
+
+/// How to build a given project into a WASI wheel
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Recipe {
+    /// Name of the project in the package index. Taken from the recipe's file name.
+    #[serde(skip)]
+    pub name: String,
+    /// Which backend builds the wheel
+    pub backend: Backend,
+    /// Requirements to pip install into the build environment before building
+    #[serde(default)]
+    pub build_requirements: Vec<String>,
+    /// Extra environment variables to set for the build command
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Extra flags appended to `RUSTFLAGS` for the build command
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+    /// PEP 517 config settings passed to `python -m build`, i.e. `setup-args=-Dfoo=bar`
+    #[serde(default)]
+    pub config_settings: Vec<String>,
+    /// Supported versions as a PEP 440 version specifier, i.e. `>=2.18.3,!=2.41.3`.
+    /// Defaults to all versions.
+    pub versions: Option<VersionSpecifiers>,
+    /// Source patches to apply to the unpacked sdist before building
+    #[serde(default)]
+    pub patches: Vec<Patch>,
+    /// Overrides for the maturin backend
+    #[serde(default)]
+    pub maturin: MaturinOptions,
+    /// Overrides for the meson-python backend
+    #[serde(default)]
+    pub meson: MesonOptions,
+}
+
+/// A unified diff to apply to the unpacked sdist before building
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Patch {
+    /// Path to the patch file, relative to the recipe file. Applied with `patch -p1`.
+    pub file: PathBuf,
+    /// Versions the patch applies to as a PEP 440 version specifier. Defaults to all versions.
+    pub versions: Option<VersionSpecifiers>,
+}
+
+/// Per-project overrides for the maturin backend
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MaturinOptions {
+    /// Cargo features to enable
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Path to the `Cargo.toml` of the extension, relative to the sdist root
+    pub manifest_path: Option<PathBuf>,
+}
+
+/// Per-project overrides for the meson-python backend
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MesonOptions {
+    /// Extra entries for the `[properties]` section of the generated cross file
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Recipe {
+    /// A recipe for building an arbitrary project with the given backend without any
+    /// customization
+    #[must_use]
+    pub fn generic(name: impl Into<String>, backend: Backend) -> Self {
+        let build_requirements = match backend {
+            Backend::Setuptools | Backend::MesonPython | Backend::ScikitBuildCore => {
+                vec!["build".to_owned()]
+            }
+            Backend::Maturin => vec!["maturin".to_owned()],
+        };
+
+        Self {
+            name: name.into(),
+            backend,
+            build_requirements,
+            env: BTreeMap::new(),
+            rustflags: vec![],
+            config_settings: vec![],
+            versions: None,
+            patches: vec![],
+            maturin: MaturinOptions::default(),
+            meson: MesonOptions::default(),
+        }
+    }
+
+    /// Parse a recipe from the contents of a TOML file
+    ///
+    /// # Errors
+    /// If the contents are not a valid recipe.
+    pub fn parse(name: impl Into<String>, contents: &str) -> anyhow::Result<Self> {
+        let name = name.into();
+        let mut recipe: Self = toml::from_str(contents)
+            .map_err(|e| anyhow::anyhow!("Invalid recipe for {name}: {e}"))?;
+        recipe.name = name;
+        Ok(recipe)
+    }
+
+    /// Whether the recipe supports building the given version
+    #[must_use]
+    pub fn supports(&self, version: &Version) -> bool {
+        self.versions
+            .as_ref()
+            .is_none_or(|versions| versions.contains(version))
+    }
+
+    /// Check that the given version can be built with this recipe
+    ///
+    /// # Errors
+    /// If the version is invalid or outside of the supported versions.
+    pub fn check_version(&self, version: &str) -> anyhow::Result<()> {
+        let parsed_version: Version = version
+            .parse()
+            .with_context(|| format!("Invalid version {version} for {}", self.name))?;
+
+        if !self.supports(&parsed_version) {
+            anyhow::bail!(
+                "{} {version} is not supported. Supported versions: {}",
+                self.name,
+                self.versions
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// All recipes available for building
+#[derive(Debug)]
+pub struct Recipes {
+    recipes: BTreeMap<String, Recipe>,
+}
+
+impl Recipes {
+    /// Load every `*.toml` recipe in the given directory.
+    /// Defaults to the "recipes" directory in this repository.
+    ///
+    /// # Errors
+    /// If the directory cannot be read or any of the recipes are invalid.
+    pub async fn load(dir: Option<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.unwrap_or_else(|| RECIPES_DIR.clone());
+        let mut entries = fs::read_dir(&dir)
+            .await
+            .map_err(|e| anyhow::anyhow!("Unable to read recipes in {}: {e}", dir.display()))?;
+
+        let mut recipes = BTreeMap::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let Some(name) = recipe_name(&path) else {
+                continue;
+            };
+            let mut recipe = Recipe::parse(name, &fs::read_to_string(&path).await?)?;
+            for patch in &mut recipe.patches {
+                patch.file = dir.join(&patch.file);
+            }
+            recipes.insert(recipe.name.clone(), recipe);
+        }
+
+        Ok(Self { recipes })
+    }
+
+    /// Find the recipe for a given project
+    ///
+    /// # Errors
+    /// If there is no recipe for the project.
+    pub fn get(&self, project: &str) -> anyhow::Result<&Recipe> {
+        self.recipes.get(project).ok_or_else(|| {
+            anyhow::anyhow!(
+                "No recipe for project {project}. Available recipes: {}",
+                self.names().collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+
+    /// Names of all projects that have a recipe
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.recipes.keys().map(String::as_str)
+    }
+}
+
+/// Project name for a recipe file, if the path is one
+fn recipe_name(path: &Path) -> Option<&str> {
+    if path.extension()? != "toml" {
+        return None;
+    }
+    path.file_stem()?.to_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_recipe() -> anyhow::Result<()> {
+        let recipe = Recipe::parse(
+            "example",
+            r#"
+            backend = "maturin"
+            build-requirements = ["maturin"]
+            rustflags = ["-C target-feature=+simd128"]
+            versions = ">=1.0"
+
+            [env]
+            FOO = "bar"
+
+            [[patches]]
+            file = "example/no-threads.patch"
+            versions = "<2"
+            "#,
+        )?;
+
+        assert_eq!(recipe.name, "example");
+        assert_eq!(recipe.backend, Backend::Maturin);
+        assert_eq!(recipe.build_requirements, ["maturin"]);
+        assert_eq!(recipe.rustflags, ["-C target-feature=+simd128"]);
+        assert_eq!(recipe.env.get("FOO").map(String::as_str), Some("bar"));
+        assert_eq!(recipe.versions.unwrap().to_string(), ">=1.0");
+        assert_eq!(
+            recipe.patches[0].file,
+            PathBuf::from("example/no-threads.patch")
+        );
+        assert_eq!(
+            recipe.patches[0].versions.as_ref().unwrap().to_string(),
+            "<2"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_supported_versions() -> anyhow::Result<()> {
+        let recipe = Recipe::parse(
+            "example",
+            r#"
+            backend = "setuptools"
+            versions = ">=2.18.3,<3,!=2.41.3"
+            "#,
+        )?;
+
+        recipe.check_version("2.18.3")?;
+        // PEP 440 ordering, not string ordering
+        recipe.check_version("2.100.0")?;
+        assert!(recipe.check_version("2.9.0").is_err());
+        assert!(recipe.check_version("2.18.3rc1").is_err());
+        assert!(recipe.check_version("2.41.3").is_err());
+        assert!(recipe.check_version("3.0.0").is_err());
+        assert!(recipe.check_version("not-a-version").is_err());
+
+        // No specifier means every version is supported
+        Recipe::generic("example", Backend::Setuptools).check_version("0.0.1")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_fields() {
+        assert!(Recipe::parse("example", "backend = \"setuptools\"\nunknown = 1").is_err());
+    }
+
+    #[tokio::test]
+    async fn load_repo_recipes() -> anyhow::Result<()> {
+        let recipes = Recipes::load(None).await?;
+
+        assert_eq!(recipes.get("pydantic-core")?.backend, Backend::Maturin);
+        assert_eq!(recipes.get("regex")?.backend, Backend::Setuptools);
+        assert!(recipes.get("not-a-project").is_err());
+
+        Ok(())
+    }
+
+    /// The package build workflow offers every recipe as a choice, and nothing else
+    #[tokio::test]
+    async fn workflow_choices_match_recipes() -> anyhow::Result<()> {
+        let recipes = Recipes::load(None).await?;
+        let workflow = tokio::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join(".github/workflows/package_build.yml"),
+        )
+        .await?;
+
+        let options = workflow
+            .lines()
+            .skip_while(|line| line.trim() != "options:")
+            .skip(1)
+            .map_while(|line| line.trim().strip_prefix("- "))
+            .collect::<Vec<_>>();
+        let mut names = recipes.names().collect::<Vec<_>>();
+        names.sort_unstable();
+
+        assert_eq!(options, names);
+
+        Ok(())
+    }
+}
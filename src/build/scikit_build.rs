@@ -0,0 +1,118 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::build::{
+    build_tools::PythonVersion,
+    recipe::Recipe,
+    setuptools::{self, Compilers, ConfigFile},
+};
+
+/// Builds a scikit-build-core project and returns the wheel path for publishing
+pub async fn build(
+    recipe: &Recipe,
+    python_version: PythonVersion,
+    package_dir: &Path,
+    version: &str,
+) -> anyhow::Result<PathBuf> {
+    setuptools::build(
+        recipe,
+        python_version,
+        package_dir,
+        version,
+        &compilers(python_version, package_dir),
+    )
+    .await
+}
+
+/// The generated toolchain file, and the config setting pointing `CMake` at it
+pub fn compilers(python_version: PythonVersion, package_dir: &Path) -> Compilers {
+    let path = package_dir.join(format!("wasi-toolchain-{python_version}.cmake"));
+    Compilers::ToolchainFile(ConfigFile {
+        setting: format!(
+            "cmake.define.CMAKE_TOOLCHAIN_FILE={}",
+            path.to_string_lossy()
+        ),
+        contents: toolchain_file(python_version),
+        path,
+    })
+}
+
+/// Generate a `CMake` toolchain file targeting WASI with the given Python version
+fn toolchain_file(python_version: PythonVersion) -> String {
+    let wasi_sdk_path = python_version.wasi_sdk_path();
+    let cross_prefix = python_version.cross_prefix();
+    let python_include = cross_prefix.join(format!("include/python{python_version}"));
+
+    let mut file = String::new();
+    let mut set = |name: &str, value: &str| {
+        writeln!(file, "set({name} \"{}\")", value.replace('"', "\\\"")).unwrap();
+    };
+
+    set("CMAKE_SYSTEM_NAME", "WASI");
+    set("CMAKE_SYSTEM_VERSION", "1");
+    set("CMAKE_SYSTEM_PROCESSOR", "wasm32");
+
+    for (name, binary) in [
+        ("CMAKE_C_COMPILER", "clang"),
+        ("CMAKE_CXX_COMPILER", "clang++"),
+        ("CMAKE_AR", "ar"),
+    ] {
+        set(
+            name,
+            &wasi_sdk_path.join("bin").join(binary).to_string_lossy(),
+        );
+    }
+    set("CMAKE_C_COMPILER_TARGET", "wasm32-wasi");
+    set("CMAKE_CXX_COMPILER_TARGET", "wasm32-wasi");
+    set(
+        "CMAKE_SYSROOT",
+        &wasi_sdk_path.join("share/wasi-sysroot").to_string_lossy(),
+    );
+    set("CMAKE_C_FLAGS_INIT", "-fPIC -D__EMSCRIPTEN__=1");
+    set("CMAKE_CXX_FLAGS_INIT", "-fPIC");
+
+    // Look for the WASI build of Python instead of the host one
+    set("Python_INCLUDE_DIR", &python_include.to_string_lossy());
+    set("Python3_INCLUDE_DIR", &python_include.to_string_lossy());
+    set("CMAKE_FIND_ROOT_PATH", &cross_prefix.to_string_lossy());
+    set("CMAKE_FIND_ROOT_PATH_MODE_PROGRAM", "NEVER");
+    for kind in ["LIBRARY", "INCLUDE", "PACKAGE"] {
+        set(&format!("CMAKE_FIND_ROOT_PATH_MODE_{kind}"), "ONLY");
+    }
+
+    // CMake doesn't think WASI supports shared libraries, which extension modules need
+    writeln!(
+        file,
+        "set_property(GLOBAL PROPERTY TARGET_SUPPORTS_SHARED_LIBS TRUE)"
+    )
+    .unwrap();
+
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_toolchain_file() {
+        let python_version = PythonVersion::Py3_13;
+        let file = toolchain_file(python_version);
+
+        assert!(file.contains("set(CMAKE_SYSTEM_NAME \"WASI\")"));
+        assert!(file.contains(&format!(
+            "set(CMAKE_C_COMPILER \"{}\")",
+            python_version
+                .wasi_sdk_path()
+                .join("bin/clang")
+                .to_string_lossy()
+        )));
+        assert!(file.contains(&format!(
+            "set(Python_INCLUDE_DIR \"{}/include/python3.13\")",
+            python_version.cross_prefix().to_string_lossy()
+        )));
+        assert!(file.ends_with("TARGET_SUPPORTS_SHARED_LIBS TRUE)\n"));
+    }
+}
@@ -0,0 +1,255 @@
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+
+use crate::{
+    build::{
+        build_tools::PythonVersion,
+        recipe::Recipe,
+        wheels::{compiler_flags, pure_python_wheel_path, python_flags, retag_wheel, wheel_path},
+    },
+    plan, run,
+    runner::{RunOptions, run_with},
+};
+
+/// How a PEP 517 backend finds the WASI SDK compilers
+#[derive(Debug)]
+pub enum Compilers {
+    /// `CC`, `CFLAGS` and the like in the environment
+    Environment,
+    /// A toolchain file, on top of the environment
+    ToolchainFile(ConfigFile),
+    /// A cross file, which is the only place compilers are set. Meson applies compiler
+    /// environment variables to the build machine when cross compiling, so they would break
+    /// native helpers.
+    CrossFile(ConfigFile),
+}
+
+/// A generated file the backend reads its compilers from
+#[derive(Debug)]
+pub struct ConfigFile {
+    /// Where the file is written
+    pub path: PathBuf,
+    /// Contents of the file
+    pub contents: String,
+    /// Config setting pointing the backend at the file
+    pub setting: String,
+}
+
+impl Compilers {
+    /// The generated file with the compilers, if there is one
+    pub fn file(&self) -> Option<&ConfigFile> {
+        match self {
+            Self::Environment => None,
+            Self::ToolchainFile(file) | Self::CrossFile(file) => Some(file),
+        }
+    }
+}
+
+/// Builds a setuptools (or other PEP 517) project with `python -m build`, on top of the config
+/// settings in the recipe. Returns the wheel path for publishing.
+pub async fn build(
+    recipe: &Recipe,
+    python_version: PythonVersion,
+    package_dir: &Path,
+    version: &str,
+    compilers: &Compilers,
+) -> anyhow::Result<PathBuf> {
+    let path_variable = python_version.create_venv(package_dir).await?;
+
+    run_with(
+        Command::new("pip")
+            .arg("install")
+            .args(&recipe.build_requirements)
+            .arg("--upgrade")
+            // Make it possible to not have to activate the venv
+            .env("PATH", &path_variable),
+        &RunOptions::network(),
+    )
+    .await?;
+
+    if let Some(file) = compilers.file() {
+        plan::write(&file.path, &file.contents).await?;
+    }
+    run(&mut command(
+        recipe,
+        python_version,
+        package_dir,
+        &path_variable,
+        compilers,
+    ))
+    .await?;
+
+    // No extension modules, so nothing to retag
+    if let Some(wheel) = pure_python_wheel_path(&recipe.name, package_dir, version)? {
+        return Ok(wheel);
+    }
+
+    let wheel = wheel_path(&recipe.name, python_version, package_dir, version);
+    retag_wheel(
+        &recipe.name,
+        python_version,
+        package_dir,
+        version,
+        &path_variable,
+    )
+    .await?;
+
+    Ok(wheel)
+}
+
+/// The `python -m build` invocation with the flags to cross compile for WASI
+pub fn command(
+    recipe: &Recipe,
+    python_version: PythonVersion,
+    package_dir: &Path,
+    path_variable: &str,
+    compilers: &Compilers,
+) -> Command {
+    let mut command = Command::new("python");
+    command.args(["-m", "build", "--wheel"]).args(
+        recipe
+            .config_settings
+            .iter()
+            .chain(compilers.file().map(|file| &file.setting))
+            .map(|setting| format!("-C{setting}")),
+    );
+    python_flags(&mut command, python_version, package_dir, path_variable);
+    if !matches!(compilers, Compilers::CrossFile(_)) {
+        compiler_flags(&mut command, python_version, package_dir);
+    }
+    command.envs(&recipe.env);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        build::Recipes,
+        index::SOURCE_DATE_EPOCH_FILE,
+        runner::fake::{RecordedCommand, RecordingRunner},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn build_regex() -> anyhow::Result<()> {
+        let recipes = Recipes::load(None).await?;
+        let recipe = recipes.get("regex")?;
+        let temp_dir = tempfile::tempdir()?;
+        let package_dir = temp_dir.path();
+        std::fs::write(package_dir.join(SOURCE_DATE_EPOCH_FILE), "1700000000")?;
+        let runner = Arc::new(RecordingRunner::building(
+            "regex-2024.11.6-cp313-cp313-linux_x86_64.whl",
+        ));
+
+        let wheel = runner
+            .record(build(
+                recipe,
+                PythonVersion::Py3_13,
+                package_dir,
+                "2024.11.6",
+                &Compilers::Environment,
+            ))
+            .await?;
+
+        assert_eq!(
+            wheel,
+            package_dir.join("dist/regex-2024.11.6-cp313-cp313-wasi_0_0_0_wasm32.whl")
+        );
+        let commands = runner.commands();
+        assert_eq!(
+            commands
+                .iter()
+                .map(RecordedCommand::line)
+                .collect::<Vec<_>>(),
+            [
+                format!(
+                    "python3.13 -m venv {}",
+                    package_dir.join(".venv-3.13").display()
+                ),
+                "pip install build --upgrade".to_owned(),
+                "python -m build --wheel".to_owned(),
+                "pip install wheel --upgrade".to_owned(),
+                format!(
+                    "wheel tags --platform-tag wasi_0_0_0_wasm32 --remove {}",
+                    package_dir
+                        .join("dist/regex-2024.11.6-cp313-cp313-linux_x86_64.whl")
+                        .display()
+                ),
+            ]
+        );
+
+        // Cross-compiled against the WASI build of CPython
+        let build = &commands[2];
+        let python_version = PythonVersion::Py3_13;
+        let cross_prefix = python_version.cross_prefix();
+        let wasi_sdk_path = python_version.wasi_sdk_path();
+        assert_eq!(build.current_dir.as_deref(), Some(package_dir));
+        for (key, value) in [
+            (
+                "CC",
+                wasi_sdk_path
+                    .join("bin/clang")
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            (
+                "LDSHARED",
+                wasi_sdk_path
+                    .join("bin/clang")
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            ("CROSS_PREFIX", cross_prefix.to_string_lossy().into_owned()),
+            (
+                "CFLAGS",
+                format!(
+                    "-I{}/include/python3.13 -D__EMSCRIPTEN__=1 -fPIC -ffile-prefix-map={}=.",
+                    cross_prefix.display(),
+                    package_dir.display()
+                ),
+            ),
+            ("LDFLAGS", "-shared".to_owned()),
+            ("SOURCE_DATE_EPOCH", "1700000000".to_owned()),
+            (
+                "_PYTHON_SYSCONFIGDATA_NAME",
+                "_sysconfigdata__wasi_wasm32-wasi".to_owned(),
+            ),
+        ] {
+            assert_eq!(build.env[key], value, "{key}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pure_python_wheels_are_not_retagged() -> anyhow::Result<()> {
+        let recipe = Recipe::parse("example", r#"backend = "setuptools""#)?;
+        let temp_dir = tempfile::tempdir()?;
+        let package_dir = temp_dir.path();
+        let runner = Arc::new(RecordingRunner::building("example-1.0-py3-none-any.whl"));
+
+        let wheel = runner
+            .record(build(
+                &recipe,
+                PythonVersion::Py3_12,
+                package_dir,
+                "1.0",
+                &Compilers::Environment,
+            ))
+            .await?;
+
+        assert_eq!(wheel, package_dir.join("dist/example-1.0-py3-none-any.whl"));
+        assert!(
+            runner
+                .commands()
+                .iter()
+                .all(|command| command.program != "wheel")
+        );
+
+        Ok(())
+    }
+}
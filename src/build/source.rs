@@ -0,0 +1,454 @@
+//! Source trees to build from: released sdists, local directories or git checkouts.
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use heck::ToSnakeCase;
+use pep440_rs::Version;
+use tokio::{fs, process::Command};
+
+use crate::{
+    build::{PACKAGES_DIR, patches::apply_patches, recipe::Recipe},
+    download_package,
+    index::{SDIST_HASH_FILE, SDIST_URL_FILE, SOURCE_DATE_EPOCH_FILE, SdistSource, sdist_version},
+    plan, run,
+    runner::{RunOptions, run_with},
+};
+
+/// Where to get the source tree for a build from
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// A released sdist of the given version from the package index
+    Release(String),
+    /// A local sdist archive, i.e. a patched or private release
+    Sdist(PathBuf),
+    /// A local source directory, i.e. a checkout of the project
+    Dir(PathBuf),
+    /// A git repository at a given ref
+    Git {
+        /// URL of the repository to clone
+        url: String,
+        /// Branch, tag or commit to check out
+        rev: String,
+    },
+}
+
+/// A source tree that has been unpacked and patched, ready to build
+#[derive(Debug)]
+pub struct PreparedSource {
+    /// Directory containing the source tree
+    pub package_dir: PathBuf,
+    /// Version of the project, taken from its metadata for non-release sources
+    pub version: String,
+    /// Local version label to add to the built wheels, if this isn't a release
+    pub local_label: Option<String>,
+    /// Sha256 of the sdist the source tree was unpacked from, if any
+    pub sdist_sha256: Option<String>,
+    /// URL of the sdist the source tree was unpacked from, if any
+    pub sdist_url: Option<String>,
+}
+
+impl Source {
+    /// Short description of the source, i.e. the version for releases
+    #[must_use]
+    pub fn label(&self) -> String {
+        match self {
+            Self::Release(version) => version.clone(),
+            Self::Sdist(_) => "sdist".to_owned(),
+            Self::Dir(_) => "local".to_owned(),
+            Self::Git { rev, .. } => format!("git-{}", rev.replace('/', "-")),
+        }
+    }
+
+    /// Unpack or copy the source tree into the output directory and apply any recipe patches.
+    ///
+    /// Local and git sources are copied, so builds never modify the original tree. Unless
+    /// forced, the version has to be supported by the recipe. It's checked as soon as it's known:
+    /// before downloading or unpacking sdists, and before building other sources.
+    ///
+    /// # Errors
+    /// If the source can't be retrieved, its version can't be determined or isn't supported, or
+    /// patches fail.
+    pub async fn prepare(
+        &self,
+        recipe: &Recipe,
+        output_dir: Option<PathBuf>,
+        force: bool,
+    ) -> anyhow::Result<PreparedSource> {
+        let output_dir = output_dir.unwrap_or_else(|| PACKAGES_DIR.clone());
+        let project = recipe.name.to_snake_case();
+
+        if plan::is_planning() && matches!(self, Self::Sdist(_) | Self::Git { .. }) {
+            bail!(
+                "The version of sdist and git sources is only known after unpacking them, so they can't be planned"
+            );
+        }
+
+        let prepared = match self {
+            Self::Release(version) => {
+                check_version(recipe, version, force)?;
+                let package_dir = download_package(
+                    &recipe.name,
+                    SdistSource::Release(version),
+                    Some(output_dir),
+                )
+                .await?;
+                PreparedSource {
+                    sdist_sha256: sdist_sha256(&package_dir).await,
+                    sdist_url: sdist_url(&package_dir).await,
+                    package_dir,
+                    version: version.clone(),
+                    local_label: None,
+                }
+            }
+            Self::Sdist(sdist) => {
+                check_version(recipe, &sdist_version(sdist)?, force)?;
+                let package_dir =
+                    download_package(&recipe.name, SdistSource::Archive(sdist), Some(output_dir))
+                        .await?;
+                PreparedSource {
+                    version: project_version(&package_dir).await?,
+                    sdist_sha256: sdist_sha256(&package_dir).await,
+                    sdist_url: sdist_url(&package_dir).await,
+                    package_dir,
+                    local_label: None,
+                }
+            }
+            Self::Dir(dir) => {
+                let version = project_version(dir).await?;
+                check_version(recipe, &version, force)?;
+                let package_dir = output_dir.join(format!("{project}-{version}+local"));
+                copy_source_tree(dir, &package_dir).await?;
+
+                PreparedSource {
+                    package_dir,
+                    version,
+                    local_label: Some("local".to_owned()),
+                    sdist_sha256: None,
+                    sdist_url: None,
+                }
+            }
+            Self::Git { url, rev } => {
+                let checkout = output_dir.join(format!("{project}-git"));
+                let commit = clone_git(url, rev, &checkout).await?;
+
+                let version = project_version(&checkout).await?;
+                check_version(recipe, &version, force)?;
+                let local_label = format!("g{commit}");
+                let package_dir = output_dir.join(format!("{project}-{version}+{local_label}"));
+                if package_dir.exists() {
+                    fs::remove_dir_all(&package_dir).await?;
+                }
+                fs::rename(&checkout, &package_dir).await?;
+
+                PreparedSource {
+                    package_dir,
+                    version,
+                    local_label: Some(local_label),
+                    sdist_sha256: None,
+                    sdist_url: None,
+                }
+            }
+        };
+
+        apply_patches(recipe, &prepared.package_dir, &prepared.version).await?;
+
+        Ok(prepared)
+    }
+}
+
+/// Clone a git repository into a fresh checkout at the given rev, and return its short commit
+async fn clone_git(url: &str, rev: &str, checkout: &Path) -> anyhow::Result<String> {
+    if checkout.exists() {
+        fs::remove_dir_all(checkout).await?;
+    }
+    if let Some(parent) = checkout.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    // The url and rev are never read as options, even if they start with `-`. Not retried, since
+    // a partial clone leaves the checkout behind for the next attempt.
+    run_with(
+        Command::new("git")
+            .args(["clone", "--quiet", "--", url])
+            .arg(checkout),
+        &RunOptions {
+            retries: 0,
+            ..RunOptions::network()
+        },
+    )
+    .await?;
+    // After `--` the rev would be a path instead, so options are ended explicitly
+    run(Command::new("git")
+        .args(["checkout", "--quiet", "--end-of-options", rev])
+        .current_dir(checkout))
+    .await?;
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .current_dir(checkout)
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!(
+            "Unable to resolve the commit of {url} at {rev}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+}
+
+/// Check that the recipe supports the version, unless forced
+fn check_version(recipe: &Recipe, version: &str, force: bool) -> anyhow::Result<()> {
+    if force {
+        return Ok(());
+    }
+    recipe
+        .check_version(version)
+        .map_err(|error| anyhow::anyhow!("{error:#}. Use --force to build it anyway."))
+}
+
+/// Copy a source tree to a fresh destination, without any previously built wheels or virtual
+/// environments
+pub async fn copy_source_tree(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    if dst.exists() {
+        plan::remove_dir_all(dst).await?;
+    }
+    plan::create_dir_all(dst).await?;
+
+    run(Command::new("cp").arg("-R").arg(src.join(".")).arg(dst)).await?;
+    if plan::is_planning() {
+        return Ok(());
+    }
+
+    // Stale wheels would otherwise be picked up instead of building, and virtual environments
+    // point at the directory they were created in
+    let mut entries = fs::read_dir(dst).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if (name == "dist" || name.starts_with(".venv-")) && entry.file_type().await?.is_dir() {
+            fs::remove_dir_all(entry.path()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sha256 of the sdist an unpacked source tree came from, if it was recorded
+async fn sdist_sha256(package_dir: &Path) -> Option<String> {
+    fs::read_to_string(package_dir.join(SDIST_HASH_FILE))
+        .await
+        .ok()
+        .map(|hash| hash.trim().to_owned())
+}
+
+/// Newest modification time in the sdist an unpacked source tree came from, if it was recorded.
+/// Copies of the source tree keep it, since the file is copied along.
+pub fn source_date_epoch(package_dir: &Path) -> Option<u64> {
+    std::fs::read_to_string(package_dir.join(SOURCE_DATE_EPOCH_FILE))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// URL of the sdist an unpacked source tree came from, if it was recorded
+async fn sdist_url(package_dir: &Path) -> Option<String> {
+    fs::read_to_string(package_dir.join(SDIST_URL_FILE))
+        .await
+        .ok()
+        .map(|url| url.trim().to_owned())
+}
+
+/// Read the version of the project in a source tree from its metadata
+pub async fn project_version(dir: &Path) -> anyhow::Result<String> {
+    // Core metadata, if this is an unpacked sdist
+    if let Ok(pkg_info) = fs::read_to_string(dir.join("PKG-INFO")).await
+        && let Some(version) = metadata_field(&pkg_info, "Version")
+    {
+        return normalize_version(version);
+    }
+
+    // Static version in pyproject.toml
+    if let Ok(pyproject) = fs::read_to_string(dir.join("pyproject.toml")).await {
+        let pyproject = toml::from_str::<toml::Table>(&pyproject)?;
+        if let Some(version) = pyproject
+            .get("project")
+            .and_then(|project| project.get("version"))
+            .and_then(toml::Value::as_str)
+        {
+            return normalize_version(version);
+        }
+    }
+
+    // Maturin projects use the version of the crate
+    if let Ok(cargo) = fs::read_to_string(dir.join("Cargo.toml")).await {
+        let cargo = toml::from_str::<toml::Table>(&cargo)?;
+        if let Some(version) = cargo
+            .get("package")
+            .and_then(|package| package.get("version"))
+            .and_then(toml::Value::as_str)
+            .or_else(|| {
+                cargo
+                    .get("workspace")
+                    .and_then(|workspace| workspace.get("package"))
+                    .and_then(|package| package.get("version"))
+                    .and_then(toml::Value::as_str)
+            })
+        {
+            return normalize_version(version);
+        }
+    }
+
+    // Otherwise let setuptools figure it out
+    if dir.join("setup.py").exists() {
+        let output = Command::new("python3")
+            .args(["setup.py", "--version"])
+            .current_dir(dir)
+            .output()
+            .await?;
+        if output.status.success()
+            && let Some(version) = String::from_utf8(output.stdout)?.lines().last()
+        {
+            return normalize_version(version.trim());
+        }
+    }
+
+    bail!(
+        "Unable to determine the version of the project in {}",
+        dir.display()
+    )
+}
+
+/// Find a field in core metadata, i.e. PKG-INFO or METADATA files
+pub fn metadata_field<'a>(metadata: &'a str, field: &str) -> Option<&'a str> {
+    metadata
+        .lines()
+        // Headers end at the first empty line
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .map(str::trim)
+}
+
+/// Normalize a version according to PEP 440, so it matches the built wheel's version
+fn normalize_version(version: &str) -> anyhow::Result<String> {
+    Ok(version.parse::<Version>()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn version_from_pyproject() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        fs::write(
+            temp_dir.path().join("pyproject.toml"),
+            "[project]\nname = \"example\"\nversion = \"1.2.3\"\n",
+        )
+        .await?;
+
+        assert_eq!(project_version(temp_dir.path()).await?, "1.2.3");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn version_from_cargo_for_dynamic_pyproject() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        fs::write(
+            temp_dir.path().join("pyproject.toml"),
+            "[project]\nname = \"example\"\ndynamic = [\"version\"]\n",
+        )
+        .await?;
+        fs::write(
+            temp_dir.path().join("Cargo.toml"),
+            "[package]\nname = \"example\"\nversion = \"2.0.0-beta.1\"\n",
+        )
+        .await?;
+
+        assert_eq!(project_version(temp_dir.path()).await?, "2.0.0b1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn version_from_pkg_info() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        fs::write(
+            temp_dir.path().join("PKG-INFO"),
+            "Metadata-Version: 2.1\nName: example\nVersion: 2024.11.6\n\nVersion: not this one\n",
+        )
+        .await?;
+
+        assert_eq!(project_version(temp_dir.path()).await?, "2024.11.6");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn copy_without_build_artifacts() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let src = temp_dir.path().join("src");
+        let dst = temp_dir.path().join("dst");
+        for dir in ["dist", ".venv-3.12", "pkg"] {
+            fs::create_dir_all(src.join(dir)).await?;
+        }
+        fs::write(src.join("dist/old.whl"), "").await?;
+        fs::write(src.join("pkg/__init__.py"), "").await?;
+        fs::write(src.join("setup.py"), "").await?;
+
+        copy_source_tree(&src, &dst).await?;
+
+        assert!(dst.join("setup.py").exists());
+        assert!(dst.join("pkg/__init__.py").exists());
+        assert!(!dst.join("dist").exists());
+        assert!(!dst.join(".venv-3.12").exists());
+        // The original tree is left alone
+        assert!(src.join("dist/old.whl").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_unsupported_local_versions() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path().join("src");
+        let output_dir = temp_dir.path().join("packages");
+        fs::create_dir_all(&dir).await?;
+        fs::write(
+            dir.join("pyproject.toml"),
+            "[project]\nname = \"example\"\nversion = \"1.2.3\"\n",
+        )
+        .await?;
+        let recipe = Recipe::parse(
+            "example",
+            r#"
+            backend = "setuptools"
+            versions = "<1"
+            "#,
+        )?;
+        let source = Source::Dir(dir);
+
+        let error = source
+            .prepare(&recipe, Some(output_dir.clone()), false)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Use --force"));
+        assert!(!output_dir.exists());
+
+        let prepared = source.prepare(&recipe, Some(output_dir), true).await?;
+        assert_eq!(prepared.version, "1.2.3");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_version() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        assert!(project_version(temp_dir.path()).await.is_err());
+
+        Ok(())
+    }
+}
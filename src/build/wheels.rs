@@ -1,50 +1,106 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
 
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use heck::ToSnakeCase;
+use sha2::{Digest, Sha256};
 use tokio::process::Command;
+use zip::{DateTime, ZipArchive, ZipWriter, write::SimpleFileOptions};
 
-use crate::run;
+use crate::{
+    plan, run,
+    runner::{RunOptions, run_with},
+};
 
-use super::{PythonVersion, SupportedProjects};
+use super::{
+    PythonVersion,
+    source::{metadata_field, source_date_epoch},
+};
 
 /// Wheel tag at the moment
 const PLATFORM_TAG: &str = "wasi_0_0_0_wasm32";
 
-/// Add environment variables for cross-compilation
+/// Add environment variables for cross-compilation: the WASI build of Python, and the compilers
+/// from [`compiler_flags`].
 pub fn default_wheel_flags<'a>(
     command: &'a mut Command,
     python_version: PythonVersion,
     package_dir: impl AsRef<Path>,
     path_variable: &str,
 ) -> &'a mut Command {
+    compiler_flags(
+        python_flags(command, python_version, &package_dir, path_variable),
+        python_version,
+        package_dir,
+    )
+}
+
+/// Add environment variables pointing the build at the WASI build of Python, without setting
+/// any compilers. For backends that get them from a cross file instead.
+///
+/// Builds are kept reproducible by setting `SOURCE_DATE_EPOCH` from the sdist.
+pub fn python_flags<'a>(
+    command: &'a mut Command,
+    python_version: PythonVersion,
+    package_dir: impl AsRef<Path>,
+    path_variable: &str,
+) -> &'a mut Command {
+    let package_dir = package_dir.as_ref();
     let cross_prefix = python_version.cross_prefix();
-    let wasi_sdk_path = python_version.wasi_sdk_path();
-    let cc = wasi_sdk_path.join("bin/clang");
 
+    if let Some(epoch) = source_date_epoch(package_dir) {
+        command.env("SOURCE_DATE_EPOCH", epoch.to_string());
+    }
     command
-        .current_dir(&package_dir)
+        .current_dir(package_dir)
         // Make it possible to not have to activate the venv
         .env("PATH", path_variable)
         .env("CROSS_PREFIX", &cross_prefix)
-        .env("WASI_SDK_PATH", &wasi_sdk_path)
+        .env("WASI_SDK_PATH", python_version.wasi_sdk_path())
         .env("SYSCONFIG", python_version.cross_lib_dir())
-        .env("CC", &cc)
-        .env("CXX", wasi_sdk_path.join("bin/clang++"))
         .env(
             "PYTHONPATH",
             cross_prefix.join(format!("lib/python{python_version}")),
         )
+        .env(
+            "_PYTHON_SYSCONFIGDATA_NAME",
+            "_sysconfigdata__wasi_wasm32-wasi",
+        )
+}
+
+/// Add environment variables to compile and link extension modules with the WASI SDK.
+///
+/// The package directory is mapped to `.` in debug info and macros like `__FILE__`, to keep
+/// builds reproducible.
+pub fn compiler_flags(
+    command: &mut Command,
+    python_version: PythonVersion,
+    package_dir: impl AsRef<Path>,
+) -> &mut Command {
+    let cross_prefix = python_version.cross_prefix();
+    let wasi_sdk_path = python_version.wasi_sdk_path();
+    let cc = wasi_sdk_path.join("bin/clang");
+    let prefix_map = prefix_map(package_dir.as_ref());
+
+    command
+        .env("CC", &cc)
+        .env("CXX", wasi_sdk_path.join("bin/clang++"))
         .env(
             "CFLAGS",
             format!(
-                "-I{}/include/python{python_version} -D__EMSCRIPTEN__=1 -fPIC",
+                "-I{}/include/python{python_version} -D__EMSCRIPTEN__=1 -fPIC {prefix_map}",
                 cross_prefix.to_str().unwrap()
             ),
         )
         .env(
             "CXXFLAGS",
             format!(
-                "-I{}/include/python{python_version}",
+                "-I{}/include/python{python_version} {prefix_map}",
                 cross_prefix.to_str().unwrap()
             ),
         )
@@ -52,27 +108,90 @@ pub fn default_wheel_flags<'a>(
         .env("AR", wasi_sdk_path.join("bin/ar"))
         .env("RANLIB", "true")
         .env("LDFLAGS", "-shared")
-        .env(
-            "_PYTHON_SYSCONFIGDATA_NAME",
-            "_sysconfigdata__wasi_wasm32-wasi",
-        )
+}
+
+/// Compiler flag mapping the package directory to `.`, so it doesn't end up in the built wheel
+pub fn prefix_map(package_dir: &Path) -> String {
+    format!("-ffile-prefix-map={}=.", package_dir.display())
 }
 
 pub fn wheel_path(
-    project: SupportedProjects,
+    project: &str,
     python_version: PythonVersion,
     package_dir: impl AsRef<Path>,
     version: &str,
 ) -> PathBuf {
     package_dir.as_ref().join(format!(
         "dist/{project}-{version}-cp{py_version}-cp{py_version}-{PLATFORM_TAG}.whl",
-        project = project.to_string().to_snake_case(),
+        project = project.to_snake_case(),
         py_version = python_version.to_string().replace('.', "")
     ))
 }
 
+/// Path to a pure Python wheel for the project, if one was built
+pub fn pure_python_wheel_path(
+    project: &str,
+    package_dir: impl AsRef<Path>,
+    version: &str,
+) -> anyhow::Result<Option<PathBuf>> {
+    let pattern = package_dir.as_ref().join(format!(
+        "dist/{project}-{version}-py*-none-any.whl",
+        project = project.to_snake_case(),
+    ));
+    Ok(glob::glob(&pattern.to_string_lossy())?.next().transpose()?)
+}
+
+/// Extension modules found inside of a wheel
+#[derive(Debug, Default)]
+pub struct WheelContents {
+    /// Extension modules compiled to WebAssembly
+    pub wasm_modules: Vec<String>,
+    /// Extension modules compiled for some other target, i.e. the host
+    pub native_modules: Vec<String>,
+}
+
+impl WheelContents {
+    /// Whether or not the wheel only contains Python code
+    pub fn is_pure_python(&self) -> bool {
+        self.wasm_modules.is_empty() && self.native_modules.is_empty()
+    }
+}
+
+/// Inspect the extension modules in a wheel to see what they were compiled for
+pub async fn inspect_wheel(wheel: impl Into<PathBuf>) -> anyhow::Result<WheelContents> {
+    const WASM_MAGIC: &[u8] = b"\0asm";
+    let wheel = wheel.into();
+
+    tokio::task::spawn_blocking(move || {
+        let mut archive = ZipArchive::new(File::open(wheel)?)?;
+        let mut contents = WheelContents::default();
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            let name = file.name()?.into_owned();
+            if !Path::new(&name)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("so"))
+            {
+                continue;
+            }
+
+            let mut magic = [0; 4];
+            let is_wasm = file.read_exact(&mut magic).is_ok() && magic == WASM_MAGIC;
+            if is_wasm {
+                contents.wasm_modules.push(name);
+            } else {
+                contents.native_modules.push(name);
+            }
+        }
+
+        Ok(contents)
+    })
+    .await?
+}
+
 pub async fn retag_wheel(
-    project: SupportedProjects,
+    project: &str,
     python_version: PythonVersion,
     package_dir: impl AsRef<Path>,
     version: &str,
@@ -80,15 +199,22 @@ pub async fn retag_wheel(
 ) -> anyhow::Result<()> {
     let wheel = wheel_path(project, python_version, package_dir, version);
     // Rewrite the wheel to the correct target
-    run(Command::new("pip")
-        .args(["install", "wheel", "--upgrade"])
-        // Make it possible to not have to activate the venv
-        .env("PATH", path_variable))
+    run_with(
+        Command::new("pip")
+            .args(["install", "wheel", "--upgrade"])
+            // Make it possible to not have to activate the venv
+            .env("PATH", path_variable),
+        &RunOptions::network(),
+    )
     .await?;
 
-    let old_wheel = glob::glob(&wheel.to_string_lossy().replace(PLATFORM_TAG, "*"))?
-        .next()
-        .ok_or(anyhow::anyhow!("Missing path"))??;
+    let pattern = wheel.to_string_lossy().replace(PLATFORM_TAG, "*");
+    let old_wheel = match glob::glob(&pattern)?.next() {
+        Some(old_wheel) => old_wheel?,
+        // Nothing was built when planning
+        None if plan::is_planning() => PathBuf::from(pattern),
+        None => anyhow::bail!("Missing path"),
+    };
 
     run(Command::new("wheel")
         .args([
@@ -104,3 +230,529 @@ pub async fn retag_wheel(
     .await?;
     Ok(())
 }
+
+/// Add a local version label to a wheel, i.e. `1.0` becomes `1.0+local`.
+///
+/// Returns the path to the relabeled wheel, which is written next to the original one.
+pub async fn relabel_wheel(
+    wheel: impl Into<PathBuf>,
+    local_label: &str,
+) -> anyhow::Result<PathBuf> {
+    let wheel = wheel.into();
+    let filename = wheel
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("Invalid wheel path")?;
+    let mut parts = filename.splitn(3, '-');
+    let (Some(project), Some(version), Some(tags)) = (parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("Invalid wheel filename {filename}");
+    };
+
+    let new_version = format!("{version}+{local_label}");
+    let old_dist_info = format!("{project}-{version}.dist-info/");
+    let new_dist_info = format!("{project}-{new_version}.dist-info/");
+    let relabeled = wheel.with_file_name(format!("{project}-{new_version}-{tags}"));
+
+    let dst = relabeled.clone();
+    tokio::task::spawn_blocking(move || {
+        rewrite_wheel(&wheel, &dst, None, |name, contents| {
+            let Some(path) = name.strip_prefix(&old_dist_info) else {
+                return Ok((name, contents));
+            };
+            let contents = if path == "METADATA" {
+                let metadata = String::from_utf8(contents)?;
+                metadata_field(&metadata, "Version").context("Missing version")?;
+                replace_metadata_field(&metadata, "Version", &new_version).into_bytes()
+            } else {
+                contents
+            };
+            Ok((format!("{new_dist_info}{path}"), contents))
+        })
+    })
+    .await??;
+
+    Ok(relabeled)
+}
+
+/// Replace the value of a core metadata header, leaving other headers that contain it and the
+/// description alone
+fn replace_metadata_field(metadata: &str, field: &str, value: &str) -> String {
+    let mut in_headers = true;
+    metadata
+        .split_inclusive('\n')
+        .map(|line| {
+            // Headers end at the first empty line
+            in_headers &= !line.trim_end_matches(['\r', '\n']).is_empty();
+            let is_field = line
+                .strip_prefix(field)
+                .is_some_and(|rest| rest.starts_with(':'));
+            if in_headers && is_field {
+                let ending = &line[line.trim_end_matches(['\r', '\n']).len()..];
+                format!("{field}: {value}{ending}")
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect()
+}
+
+/// Rewrite a wheel deterministically and in place: entries are sorted, with the `.dist-info`
+/// directory last, and get the sdist's modification time and normalized permissions.
+pub async fn normalize_wheel(
+    wheel: impl Into<PathBuf>,
+    source_date_epoch: Option<u64>,
+) -> anyhow::Result<()> {
+    let wheel = wheel.into();
+    let last_modified = source_date_epoch.map_or_else(DateTime::default, zip_datetime);
+
+    tokio::task::spawn_blocking(move || {
+        let normalized = wheel.with_extension("whl.normalized");
+        rewrite_wheel(
+            &wheel,
+            &normalized,
+            Some(last_modified),
+            |name, contents| Ok((name, contents)),
+        )?;
+        std::fs::rename(&normalized, &wheel)?;
+        anyhow::Ok(())
+    })
+    .await?
+}
+
+/// Describe how two wheels differ, entry by entry, to explain why a build isn't reproducible
+pub async fn diff_wheels(
+    first: impl Into<PathBuf>,
+    second: impl Into<PathBuf>,
+) -> anyhow::Result<Vec<String>> {
+    let (first, second) = (first.into(), second.into());
+    tokio::task::spawn_blocking(move || {
+        let first_entries = wheel_entries(&first)?;
+        let second_entries = wheel_entries(&second)?;
+
+        let mut differences = vec![];
+        for (name, entry) in &first_entries {
+            let Some((_, other)) = second_entries.iter().find(|(other, _)| other == name) else {
+                differences.push(format!("{name} is only in {}", first.display()));
+                continue;
+            };
+            if entry.sha256 != other.sha256 {
+                differences.push(format!("{name} has different contents"));
+            }
+            if entry.last_modified != other.last_modified {
+                differences.push(format!(
+                    "{name} was modified at {} and {}",
+                    entry.last_modified, other.last_modified
+                ));
+            }
+            if entry.unix_mode != other.unix_mode {
+                differences.push(format!("{name} has different permissions"));
+            }
+        }
+        for (name, _) in &second_entries {
+            if !first_entries.iter().any(|(other, _)| other == name) {
+                differences.push(format!("{name} is only in {}", second.display()));
+            }
+        }
+        if differences.is_empty() {
+            let order = |entries: &[(String, WheelEntry)]| {
+                entries
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>()
+            };
+            differences.push(if order(&first_entries) == order(&second_entries) {
+                "Zip headers differ".to_owned()
+            } else {
+                "Entries are in a different order".to_owned()
+            });
+        }
+
+        Ok(differences)
+    })
+    .await?
+}
+
+/// What can differ between two builds of the same entry in a wheel
+#[derive(Debug, PartialEq, Eq)]
+struct WheelEntry {
+    sha256: String,
+    last_modified: String,
+    unix_mode: Option<u32>,
+}
+
+/// Every entry in a wheel, in the order they are stored
+fn wheel_entries(wheel: &Path) -> anyhow::Result<Vec<(String, WheelEntry)>> {
+    let mut archive = ZipArchive::new(File::open(wheel)?)?;
+    let mut entries = vec![];
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        entries.push((
+            file.name()?.into_owned(),
+            WheelEntry {
+                sha256: format!("{:x}", Sha256::digest(&contents)),
+                last_modified: file
+                    .last_modified()
+                    .map(|time| time.to_string())
+                    .unwrap_or_default(),
+                unix_mode: file.unix_mode(),
+            },
+        ));
+    }
+    Ok(entries)
+}
+
+/// Zip timestamp for seconds since the Unix epoch, clamped to the range zip can represent
+fn zip_datetime(epoch: u64) -> DateTime {
+    let days = epoch / 86_400;
+    let seconds = epoch % 86_400;
+    // Civil date from days since 1970-01-01, from Howard Hinnant's `civil_from_days`
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    let (Ok(year), Ok(month), Ok(day)) =
+        (u16::try_from(year), u8::try_from(month), u8::try_from(day))
+    else {
+        return DateTime::default();
+    };
+    DateTime::from_date_and_time(
+        year,
+        month,
+        day,
+        (seconds / 3600) as u8,
+        (seconds % 3600 / 60) as u8,
+        (seconds % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Rewrite a wheel into a new file, mapping the name and contents of every entry. Directory
+/// entries are mapped with empty contents, which are ignored. The `RECORD` file is regenerated to
+/// match the new contents.
+///
+/// Entries are written sorted by name, with the `.dist-info` directory last, and with normalized
+/// permissions, so the same contents always give the same bytes. They keep their modification
+/// time unless another one is given.
+fn rewrite_wheel(
+    src: &Path,
+    dst: &Path,
+    last_modified: Option<DateTime>,
+    mut map: impl FnMut(String, Vec<u8>) -> anyhow::Result<(String, Vec<u8>)>,
+) -> anyhow::Result<()> {
+    let mut archive = ZipArchive::new(File::open(src)?)?;
+    let mut writer = ZipWriter::new(File::create(dst)?);
+    let mut record = String::new();
+    let mut record_name = None;
+
+    let mut entries = (0..archive.len())
+        .map(|index| {
+            let name = archive.name_for_index(index).context("Missing entry")??;
+            Ok((name.into_owned(), index))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    entries.sort_by_key(|(name, _)| {
+        let in_dist_info = name
+            .split_once('/')
+            .is_some_and(|(dir, _)| dir.ends_with(".dist-info"));
+        (in_dist_info, name.clone())
+    });
+
+    for (_, index) in entries {
+        let mut file = archive.by_index(index)?;
+        let name = file.name()?.into_owned();
+        let executable = file.unix_mode().is_some_and(|mode| mode & 0o111 != 0);
+        let options = SimpleFileOptions::default()
+            .compression_method(file.compression())
+            .last_modified_time(
+                last_modified
+                    .or_else(|| file.last_modified())
+                    .unwrap_or_default(),
+            )
+            .unix_permissions(if executable { 0o755 } else { 0o644 });
+        if file.is_dir() {
+            let (name, _) = map(name, vec![])?;
+            writer.add_directory(name, options)?;
+            continue;
+        }
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        let (name, contents) = map(name, contents)?;
+
+        // Written last, once all of the hashes are known
+        if name.ends_with(".dist-info/RECORD") {
+            record_name = Some((name, options));
+            continue;
+        }
+
+        writeln!(
+            record,
+            "{name},sha256={},{}",
+            URL_SAFE_NO_PAD.encode(Sha256::digest(&contents)),
+            contents.len()
+        )
+        .unwrap();
+        writer.start_file(name, options)?;
+        writer.write_all(&contents)?;
+    }
+
+    let (record_name, options) = record_name.context("Wheel is missing a RECORD file")?;
+    writeln!(record, "{record_name},,").unwrap();
+    writer.start_file(record_name, options)?;
+    writer.write_all(record.as_bytes())?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::runner::fake::RecordingRunner;
+
+    use super::*;
+
+    fn write_wheel(path: &Path, files: &[(&str, &[u8])]) -> anyhow::Result<()> {
+        let mut writer = ZipWriter::new(File::create(path)?);
+        for (name, contents) in files {
+            if name.ends_with('/') {
+                writer.add_directory(*name, SimpleFileOptions::default())?;
+                continue;
+            }
+            writer.start_file(*name, SimpleFileOptions::default())?;
+            writer.write_all(contents)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn read_file(wheel: &Path, name: &str) -> anyhow::Result<String> {
+        let mut archive = ZipArchive::new(File::open(wheel)?)?;
+        let mut contents = String::new();
+        archive.by_name(name)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    #[tokio::test]
+    async fn inspect_wheel_modules() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let wheel = temp_dir.path().join("example-1.0-cp312-cp312-any.whl");
+
+        let mut writer = ZipWriter::new(File::create(&wheel)?);
+        for (name, contents) in [
+            ("example/__init__.py", b"".as_slice()),
+            (
+                "example/_wasm.cpython-312-wasm32-wasi.so",
+                b"\0asm\x01\0\0\0",
+            ),
+            (
+                "example/_native.cpython-312-x86_64-linux-gnu.so",
+                b"\x7fELF",
+            ),
+        ] {
+            writer.start_file(name, SimpleFileOptions::default())?;
+            writer.write_all(contents)?;
+        }
+        writer.finish()?;
+
+        let contents = inspect_wheel(&wheel).await?;
+
+        assert_eq!(
+            contents.wasm_modules,
+            ["example/_wasm.cpython-312-wasm32-wasi.so"]
+        );
+        assert_eq!(
+            contents.native_modules,
+            ["example/_native.cpython-312-x86_64-linux-gnu.so"]
+        );
+        assert!(!contents.is_pure_python());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn relabel_wheel_version() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let wheel = temp_dir
+            .path()
+            .join("example-1.0-cp312-cp312-wasi_0_0_0_wasm32.whl");
+        write_wheel(
+            &wheel,
+            &[
+                ("example/__init__.py", b"print('hi')\n"),
+                ("example-1.0.dist-info/", b""),
+                (
+                    "example-1.0.dist-info/METADATA",
+                    b"Metadata-Version: 2.1\nName: example\nVersion: 1.0\n",
+                ),
+                ("example-1.0.dist-info/RECORD", b"stale"),
+            ],
+        )?;
+
+        let relabeled = relabel_wheel(&wheel, "local").await?;
+
+        assert_eq!(
+            relabeled.file_name().unwrap(),
+            "example-1.0+local-cp312-cp312-wasi_0_0_0_wasm32.whl"
+        );
+        assert_eq!(
+            read_file(&relabeled, "example-1.0+local.dist-info/METADATA")?,
+            "Metadata-Version: 2.1\nName: example\nVersion: 1.0+local\n"
+        );
+        assert_eq!(
+            read_file(&relabeled, "example-1.0+local.dist-info/RECORD")?,
+            format!(
+                "example/__init__.py,sha256={},12\n\
+                 example-1.0+local.dist-info/METADATA,sha256={},55\n\
+                 example-1.0+local.dist-info/RECORD,,\n",
+                URL_SAFE_NO_PAD.encode(Sha256::digest(b"print('hi')\n")),
+                URL_SAFE_NO_PAD.encode(Sha256::digest(
+                    b"Metadata-Version: 2.1\nName: example\nVersion: 1.0+local\n"
+                )),
+            )
+        );
+        // The directory entry is renamed along with the files in it
+        let archive = ZipArchive::new(File::open(&relabeled)?)?;
+        assert_eq!(
+            archive.file_names().collect::<Result<Vec<_>, _>>()?,
+            [
+                "example/__init__.py",
+                "example-1.0+local.dist-info/",
+                "example-1.0+local.dist-info/METADATA",
+                "example-1.0+local.dist-info/RECORD",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_version_header_only() {
+        // The version also appears in the metadata version and the description
+        let metadata = "Metadata-Version: 2.1\nName: example\nVersion: 2.1\n\nVersion: 2.1\n";
+        assert_eq!(
+            replace_metadata_field(metadata, "Version", "2.1+local"),
+            "Metadata-Version: 2.1\nName: example\nVersion: 2.1+local\n\nVersion: 2.1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn normalize_wheel_entries() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let first = temp_dir.path().join("first.whl");
+        let second = temp_dir.path().join("second.whl");
+        let files: [(&str, &[u8]); 4] = [
+            ("example-1.0.dist-info/RECORD", b"stale"),
+            ("example-1.0.dist-info/METADATA", b"Name: example\n"),
+            ("example/b.py", b"b = 2\n"),
+            ("example/a.py", b"a = 1\n"),
+        ];
+        write_wheel(&first, &files)?;
+        let mut reversed = files;
+        reversed.reverse();
+        write_wheel(&second, &reversed)?;
+        assert!(!diff_wheels(&first, &second).await?.is_empty());
+
+        for wheel in [&first, &second] {
+            normalize_wheel(wheel, Some(1_700_000_000)).await?;
+        }
+
+        assert_eq!(std::fs::read(&first)?, std::fs::read(&second)?);
+        let mut archive = ZipArchive::new(File::open(&first)?)?;
+        assert_eq!(
+            archive.file_names().collect::<Result<Vec<_>, _>>()?,
+            [
+                "example/a.py",
+                "example/b.py",
+                "example-1.0.dist-info/METADATA",
+                "example-1.0.dist-info/RECORD"
+            ]
+        );
+        assert_eq!(
+            archive.by_index(0)?.last_modified(),
+            Some(DateTime::from_date_and_time(2023, 11, 14, 22, 13, 20)?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_timestamps() -> anyhow::Result<()> {
+        assert_eq!(
+            zip_datetime(951_782_400),
+            DateTime::from_date_and_time(2000, 2, 29, 0, 0, 0)?
+        );
+        // Before zip timestamps start
+        assert_eq!(zip_datetime(0), DateTime::default());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retag_built_wheel() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let package_dir = temp_dir.path();
+        let runner = Arc::new(RecordingRunner::default());
+
+        // Nothing was built
+        assert!(
+            runner
+                .record(retag_wheel(
+                    "pydantic-core",
+                    PythonVersion::Py3_12,
+                    package_dir,
+                    "2.27.2",
+                    "/venv/bin"
+                ))
+                .await
+                .is_err()
+        );
+
+        let built = package_dir.join("dist/pydantic_core-2.27.2-cp312-cp312-any.whl");
+        std::fs::create_dir_all(built.parent().unwrap())?;
+        std::fs::write(&built, "")?;
+        runner
+            .record(retag_wheel(
+                "pydantic-core",
+                PythonVersion::Py3_12,
+                package_dir,
+                "2.27.2",
+                "/venv/bin",
+            ))
+            .await?;
+
+        let commands = runner.commands();
+        let retag = commands.last().unwrap();
+        assert_eq!(
+            retag.line(),
+            format!(
+                "wheel tags --platform-tag {PLATFORM_TAG} --remove {}",
+                built.display()
+            )
+        );
+        assert_eq!(retag.env["PATH"], "/venv/bin");
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|command| command.line() == "pip install wheel --upgrade")
+                .count(),
+            2
+        );
+
+        Ok(())
+    }
+}
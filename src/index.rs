@@ -1,8 +1,10 @@
 use std::{
     collections::HashMap,
-    path::{Path, PathBuf},
+    io::Read,
+    path::{Component, Path, PathBuf},
 };
 
+use anyhow::{Context, bail};
 use bytes::Bytes;
 use flate2::bufread::GzDecoder;
 use heck::ToSnakeCase;
@@ -11,25 +13,59 @@ use serde::Deserialize;
 use serde_json::Value;
 use sha2::{Digest, Sha256};
 use tar::Archive;
+use tokio::fs;
+use url::Url;
 use wasi::GitHubReleaseClient;
 
+mod outdated;
 mod wasi;
 
-use crate::build::{INDEX_DIR, PACKAGES_DIR};
+pub use outdated::{ProjectStatus, ReleaseStatus};
 
-/// Download the sdist package for the specified project and version
+use crate::{
+    build::{INDEX_DIR, PACKAGES_DIR, Recipes, metadata_field},
+    plan::{self, Action},
+};
+
+/// File in an unpacked sdist with the sha256 of the archive it was unpacked from
+pub const SDIST_HASH_FILE: &str = ".wasi-wheels-sdist";
+
+/// File in an unpacked sdist with the URL of the archive it was unpacked from
+pub const SDIST_URL_FILE: &str = ".wasi-wheels-sdist-url";
+
+/// File in an unpacked sdist with the newest modification time in the archive, to use as
+/// `SOURCE_DATE_EPOCH`
+pub const SOURCE_DATE_EPOCH_FILE: &str = ".wasi-wheels-source-date-epoch";
+
+/// Where the sdist of a package comes from
+#[derive(Debug, Clone, Copy)]
+pub enum SdistSource<'a> {
+    /// A released version on the package index
+    Release(&'a str),
+    /// A local sdist archive, i.e. a patched or private one. Any previously unpacked copy of the
+    /// same sdist is replaced.
+    Archive(&'a Path),
+}
+
+/// Download the sdist package for the specified project and version, or unpack a local one
 ///
 /// Returns the path to the downloaded package.
 ///
 /// # Errors
-/// Will error if the project or version cannot be found or unpacked.
+/// Will error if the project or version cannot be found or unpacked, or a local archive is not
+/// an sdist of the project.
 pub async fn download_package(
     project: &str,
-    release_version: &str,
+    sdist: SdistSource<'_>,
     output_dir: Option<PathBuf>,
 ) -> anyhow::Result<PathBuf> {
     let output_dir = output_dir.unwrap_or_else(|| PACKAGES_DIR.clone());
 
+    let release_version = match sdist {
+        SdistSource::Release(release_version) => release_version,
+        SdistSource::Archive(sdist) => return unpack_sdist(project, sdist, output_dir).await,
+    };
+
     let default_path = output_dir.join(format!("{project}-{release_version}"));
     let snake_case_path = output_dir.join(format!("{}-{release_version}", project.to_snake_case()));
 
@@ -39,15 +75,86 @@ pub async fn download_package(
         return Ok(snake_case_path);
     }
 
-    PythonPackageIndex::default()
+    let sdist = PythonPackageIndex::default()
         .project(project)
         .await?
         .sdist(release_version)
         .ok_or(anyhow::anyhow!(
             "No version {release_version} for project {project}"
-        ))?
-        .download_sdist_and_unpack(output_dir)
-        .await
+        ))?;
+    if plan::record(Action::Download {
+        url: sdist.url.clone(),
+        to: snake_case_path.clone(),
+    }) {
+        return Ok(snake_case_path);
+    }
+    sdist.download_sdist_and_unpack(output_dir).await
+}
+
+/// Unpack a local sdist archive for the specified project, replacing any previously unpacked
+/// copy
+async fn unpack_sdist(project: &str, sdist: &Path, output_dir: PathBuf) -> anyhow::Result<PathBuf> {
+    let bytes = Bytes::from(
+        fs::read(sdist)
+            .await
+            .with_context(|| format!("Unable to read sdist {}", sdist.display()))?,
+    );
+
+    let pkg_info = sdist_pkg_info(&bytes)
+        .with_context(|| format!("{} is not a valid sdist", sdist.display()))?;
+    let name = metadata_field(&pkg_info, "Name").context("PKG-INFO is missing a name")?;
+    if normalize_name(name) != normalize_name(project) {
+        bail!("{} is an sdist for {name}, not {project}", sdist.display());
+    }
+
+    let root = output_dir.join(archive_root(&bytes)?);
+    if root.exists() {
+        fs::remove_dir_all(&root).await?;
+    }
+    let dst = output_dir.clone();
+    let path = tokio::task::spawn_blocking(move || extract_archive(&bytes, dst)).await??;
+    let package_dir = output_dir.join(path);
+    if let Ok(url) = Url::from_file_path(fs::canonicalize(sdist).await?) {
+        fs::write(package_dir.join(SDIST_URL_FILE), url.as_str()).await?;
+    }
+
+    Ok(package_dir)
+}
+
+/// Read the version from the `PKG-INFO` of a local sdist archive, without unpacking it
+///
+/// # Errors
+/// Will error if the archive cannot be read, or has no version in its `PKG-INFO`.
+pub fn sdist_version(sdist: &Path) -> anyhow::Result<String> {
+    let bytes = Bytes::from(
+        std::fs::read(sdist)
+            .with_context(|| format!("Unable to read sdist {}", sdist.display()))?,
+    );
+    let pkg_info = sdist_pkg_info(&bytes)
+        .with_context(|| format!("{} is not a valid sdist", sdist.display()))?;
+    metadata_field(&pkg_info, "Version")
+        .map(str::to_owned)
+        .context("PKG-INFO is missing a version")
+}
+
+/// The `PKG-INFO` in the top-level directory of a gzipped sdist archive
+fn sdist_pkg_info(bytes: &Bytes) -> anyhow::Result<String> {
+    let pkg_info = archive_root(bytes)?.join("PKG-INFO");
+    let mut archive = Archive::new(GzDecoder::new(&bytes[..]));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.components().eq(pkg_info.components()) {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            return Ok(contents);
+        }
+    }
+    bail!("Missing PKG-INFO")
+}
+
+/// Normalize a project name according to PEP 503
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase().replace(['_', '.'], "-")
 }
 
 /// Generates the index for a given repo at the given path
@@ -68,6 +175,45 @@ pub async fn generate_index(
         .await
 }
 
+/// List the versions of a project that have an sdist available on the package index
+///
+/// # Errors
+/// If the project information can't be retrieved.
+pub async fn released_versions(project: &str) -> anyhow::Result<Vec<String>> {
+    Ok(PythonPackageIndex::default()
+        .project(project)
+        .await?
+        .sdist_files()
+        .into_keys()
+        .collect())
+}
+
+/// Compare the upstream releases of every project with a recipe with the wheels published in
+/// the given repo
+///
+/// # Errors
+/// If it cannot download release information from either the package index or the repo.
+pub async fn outdated(
+    owner: &str,
+    repo: &str,
+    recipes: &Recipes,
+) -> anyhow::Result<Vec<ProjectStatus>> {
+    let packages = GitHubReleaseClient::new().packages(owner, repo).await?;
+    let index = PythonPackageIndex::default();
+
+    let mut statuses = vec![];
+    for project in recipes.names() {
+        let upstream = index.project(project).await?.sdist_files().into_keys();
+        statuses.push(ProjectStatus::new(
+            recipes.get(project)?,
+            upstream,
+            packages.releases(project),
+        ));
+    }
+
+    Ok(statuses)
+}
+
 /// Registry information for a given Python project in the registry
 #[derive(Debug, Deserialize)]
 struct Project {
@@ -168,25 +314,81 @@ impl ProjectFile {
         let output_dir = dst.clone();
         let path =
             tokio::task::spawn_blocking(move || extract_archive(&bytes, output_dir)).await??;
+        let package_dir = dst.join(path);
+        fs::write(package_dir.join(SDIST_URL_FILE), &self.url).await?;
 
-        Ok(dst.join(path))
+        Ok(package_dir)
     }
 }
 
 fn extract_archive(bytes: &Bytes, dst: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
     // Find out the path we are extracting to
-    let mut archive = Archive::new(GzDecoder::new(&bytes[..]));
-    let entry = archive.entries()?.next().unwrap()?;
-    let path = match entry.path()?.parent() {
-        Some(path) if path.to_str() == Some("") => entry.path()?.into_owned(),
-        Some(path) => path.to_owned(),
-        None => return Err(anyhow::anyhow!("Invalid archive")),
-    };
+    let path = archive_root(bytes)?;
     // Actually extract. New archive because we've already read entries in the previous step
-    Archive::new(GzDecoder::new(&bytes[..])).unpack(dst)?;
+    Archive::new(GzDecoder::new(&bytes[..])).unpack(&dst)?;
+    // Remember which sdist this is, so builds can be cached by it
+    std::fs::write(
+        dst.as_ref().join(&path).join(SDIST_HASH_FILE),
+        format!("{:x}", Sha256::digest(bytes)),
+    )?;
+    // So timestamps in the built wheels only depend on the sdist
+    std::fs::write(
+        dst.as_ref().join(&path).join(SOURCE_DATE_EPOCH_FILE),
+        archive_mtime(bytes)?.to_string(),
+    )?;
     Ok(path)
 }
 
+/// The newest modification time of the entries in a gzipped tar archive
+fn archive_mtime(bytes: &Bytes) -> anyhow::Result<u64> {
+    let mut archive = Archive::new(GzDecoder::new(&bytes[..]));
+    let mut newest = 0;
+    for entry in archive.entries()? {
+        newest = newest.max(entry?.header().mtime()?);
+    }
+    Ok(newest)
+}
+
+/// The top-level directory of a gzipped tar archive. Every entry has to be inside of it, so
+/// it can be replaced without touching anything else.
+fn archive_root(bytes: &Bytes) -> anyhow::Result<PathBuf> {
+    let mut archive = Archive::new(GzDecoder::new(&bytes[..]));
+    let mut root: Option<PathBuf> = None;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?;
+        let mut components = path
+            .components()
+            .filter(|component| !matches!(component, Component::CurDir));
+        let first = match components.next() {
+            // `./` itself
+            None => continue,
+            Some(Component::Normal(first)) => first,
+            Some(_) => bail!("Archive entry {} is not a relative path", path.display()),
+        };
+        let mut rest = components.peekable();
+        if rest.peek().is_none() && !entry.header().entry_type().is_dir() {
+            bail!(
+                "Archive has a file {} outside of a directory",
+                path.display()
+            );
+        }
+        if rest.any(|component| !matches!(component, Component::Normal(_))) {
+            bail!("Archive entry {} is not a normalized path", path.display());
+        }
+        match &root {
+            None => root = Some(first.into()),
+            Some(root) if root.as_os_str() == first => {}
+            Some(root) => bail!(
+                "Archive has more than one top-level directory: {} and {}",
+                root.display(),
+                first.display()
+            ),
+        }
+    }
+    root.context("Empty archive")
+}
+
 /// Hashes available for validating the file contents
 #[derive(Debug, Deserialize)]
 struct Hashes {
@@ -243,7 +445,6 @@ impl PythonPackageIndex {
 mod tests {
     use itertools::Itertools;
     use tempfile::tempdir;
-    use tokio::fs;
 
     use super::*;
 
@@ -270,6 +471,137 @@ mod tests {
         Ok(())
     }
 
+    fn sdist(root: &str, pkg_info: &str) -> anyhow::Result<Vec<u8>> {
+        archive(&[(&format!("{root}/PKG-INFO"), Some(pkg_info))])
+    }
+
+    /// A gzipped tar archive with the given files, or directories if they have no contents
+    fn archive(entries: &[(&str, Option<&str>)]) -> anyhow::Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mtime(1_700_000_000);
+            if let Some(contents) = contents {
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                builder.append_data(&mut header, path, contents.as_bytes())?;
+            } else {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                header.set_mode(0o755);
+                builder.append_data(&mut header, path, &[][..])?;
+            }
+        }
+        Ok(builder.into_inner()?.finish()?)
+    }
+
+    #[tokio::test]
+    async fn can_unpack_local_sdist() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let sdist_path = tempdir.path().join("example_project-1.0.tar.gz");
+        fs::write(
+            &sdist_path,
+            sdist(
+                "example_project-1.0",
+                "Metadata-Version: 2.1\nName: example.project\nVersion: 1.0\n",
+            )?,
+        )
+        .await?;
+        let output_dir = tempdir.path().join("packages");
+        // A stale copy is replaced
+        fs::create_dir_all(output_dir.join("example_project-1.0")).await?;
+        fs::write(output_dir.join("example_project-1.0/stale.txt"), "").await?;
+
+        let package_dir = download_package(
+            "example-project",
+            SdistSource::Archive(&sdist_path),
+            Some(output_dir.clone()),
+        )
+        .await?;
+
+        assert_eq!(package_dir, output_dir.join("example_project-1.0"));
+        assert!(package_dir.join("PKG-INFO").exists());
+        assert!(!package_dir.join("stale.txt").exists());
+        // The archive hash is recorded for the build cache
+        assert_eq!(
+            fs::read_to_string(package_dir.join(SDIST_HASH_FILE)).await?,
+            format!("{:x}", Sha256::digest(fs::read(&sdist_path).await?))
+        );
+        assert!(
+            fs::read_to_string(package_dir.join(SDIST_URL_FILE))
+                .await?
+                .starts_with("file:///")
+        );
+        assert_eq!(
+            fs::read_to_string(package_dir.join(SOURCE_DATE_EPOCH_FILE)).await?,
+            "1700000000"
+        );
+        assert!(
+            download_package(
+                "other-project",
+                SdistSource::Archive(&sdist_path),
+                Some(output_dir)
+            )
+            .await
+            .is_err()
+        );
+        assert_eq!(sdist_version(&sdist_path)?, "1.0");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_unpack_sdists_with_one_top_level_directory() -> anyhow::Result<()> {
+        let pkg_info = Some("Metadata-Version: 2.1\nName: example\nVersion: 1.0\n");
+        let tempdir = tempdir()?;
+        let output_dir = tempdir.path().join("packages");
+        fs::create_dir_all(output_dir.join("other-1.0")).await?;
+        fs::write(output_dir.join("PKG-INFO"), "").await?;
+
+        // `./` is skipped
+        let bytes = Bytes::from(archive(&[
+            ("./", None),
+            ("./example-1.0/", None),
+            ("./example-1.0/PKG-INFO", pkg_info),
+        ])?);
+        assert_eq!(archive_root(&bytes)?, Path::new("example-1.0"));
+        // Entries deeper than the root
+        let bytes = Bytes::from(archive(&[
+            ("example-1.0/src/a.c", Some("")),
+            ("example-1.0/PKG-INFO", pkg_info),
+        ])?);
+        assert_eq!(archive_root(&bytes)?, Path::new("example-1.0"));
+
+        for entries in [
+            vec![("PKG-INFO", pkg_info), ("example-1.0/PKG-INFO", pkg_info)],
+            vec![
+                ("example-1.0/PKG-INFO", pkg_info),
+                ("other-1.0/a.c", Some("")),
+            ],
+            vec![("./", None)],
+        ] {
+            let sdist_path = tempdir.path().join("example-1.0.tar.gz");
+            fs::write(&sdist_path, archive(&entries)?).await?;
+            assert!(
+                download_package(
+                    "example",
+                    SdistSource::Archive(&sdist_path),
+                    Some(output_dir.clone())
+                )
+                .await
+                .is_err()
+            );
+        }
+        // Nothing was touched
+        assert!(output_dir.join("other-1.0").exists());
+        assert!(output_dir.join("PKG-INFO").exists());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn can_download_specific_project_sdist_file() -> anyhow::Result<()> {
         let tempdir = tempdir()?;
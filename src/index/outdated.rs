@@ -0,0 +1,235 @@
+//! Compare the releases on the package index with the wheels that have been published.
+use std::{collections::HashMap, fmt};
+
+use pep440_rs::Version;
+use serde::Serialize;
+use strum::IntoEnumIterator;
+
+use crate::build::{PythonVersion, Recipe};
+
+/// How the published wheels of a project compare with its upstream releases
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProjectStatus {
+    /// Name of the project
+    pub project: String,
+    /// Newest non pre-release version with an sdist upstream that the recipe supports
+    pub latest_upstream: Option<String>,
+    /// Newest version with a published release
+    pub latest_published: Option<String>,
+    /// Whether there is a newer upstream version than the latest published one
+    pub outdated: bool,
+    /// Every published release, oldest first
+    pub releases: Vec<ReleaseStatus>,
+}
+
+/// Which wheels a published release has
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReleaseStatus {
+    /// Version of the release
+    pub version: String,
+    /// Supported Python versions that don't have a wheel in this release
+    pub missing_python_versions: Vec<String>,
+}
+
+impl ProjectStatus {
+    /// Join the upstream versions the recipe supports with the wheel filenames of each published
+    /// release
+    pub fn new(
+        recipe: &Recipe,
+        upstream: impl IntoIterator<Item = String>,
+        published: Option<&HashMap<String, Vec<String>>>,
+    ) -> Self {
+        let latest_upstream = upstream
+            .into_iter()
+            .filter_map(|version| Some((version.parse::<Version>().ok()?, version)))
+            .filter(|(parsed, _)| !parsed.any_prerelease() && recipe.supports(parsed))
+            .max()
+            .map(|(_, version)| version);
+
+        let mut releases = published
+            .into_iter()
+            .flatten()
+            .map(|(version, wheels)| {
+                (
+                    version.parse::<Version>().ok(),
+                    ReleaseStatus {
+                        version: version.clone(),
+                        missing_python_versions: missing_python_versions(wheels),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        releases.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let releases = releases
+            .into_iter()
+            .map(|(_, release)| release)
+            .collect::<Vec<_>>();
+
+        let latest_published = releases.last().map(|release| release.version.clone());
+
+        Self {
+            project: recipe.name.clone(),
+            outdated: is_outdated(latest_upstream.as_deref(), latest_published.as_deref()),
+            latest_upstream,
+            latest_published,
+            releases,
+        }
+    }
+}
+
+/// Whether the upstream version is newer than the published one
+fn is_outdated(latest_upstream: Option<&str>, latest_published: Option<&str>) -> bool {
+    match (latest_upstream, latest_published) {
+        (Some(upstream), Some(published)) => {
+            match (upstream.parse::<Version>(), published.parse::<Version>()) {
+                (Ok(upstream), Ok(published)) => upstream > published,
+                _ => upstream != published,
+            }
+        }
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+impl fmt::Display for ProjectStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.outdated {
+            "outdated"
+        } else {
+            "up to date"
+        };
+        writeln!(f, "{} ({status})", self.project)?;
+        writeln!(
+            f,
+            "  latest upstream:  {}",
+            self.latest_upstream.as_deref().unwrap_or("none")
+        )?;
+        writeln!(
+            f,
+            "  latest published: {}",
+            self.latest_published.as_deref().unwrap_or("none")
+        )?;
+        for release in &self.releases {
+            if !release.missing_python_versions.is_empty() {
+                writeln!(
+                    f,
+                    "  {} is missing Python {}",
+                    release.version,
+                    release.missing_python_versions.join(", ")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Supported Python versions that none of the wheels were built for.
+/// Pure Python wheels cover every version.
+fn missing_python_versions(wheels: &[String]) -> Vec<String> {
+    let python_tags = wheels
+        .iter()
+        .filter_map(|wheel| {
+            // {name}-{version}(-{build})?-{python}-{abi}-{platform}.whl
+            wheel.strip_suffix(".whl")?.rsplit('-').nth(2)
+        })
+        .flat_map(|tags| tags.split('.'))
+        .collect::<Vec<_>>();
+
+    if python_tags.iter().any(|tag| tag.starts_with("py3")) {
+        return vec![];
+    }
+
+    PythonVersion::iter()
+        .map(|python_version| python_version.to_string())
+        .filter(|python_version| {
+            let tag = format!("cp{}", python_version.replace('.', ""));
+            !python_tags.contains(&tag.as_str())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_with_upstream() {
+        let published = HashMap::from([
+            (
+                "2.27.2".to_owned(),
+                vec![
+                    "pydantic_core-2.27.2-cp312-cp312-wasi_0_0_0_wasm32.whl".to_owned(),
+                    "pydantic_core-2.27.2-cp313-cp313-wasi_0_0_0_wasm32.whl".to_owned(),
+                ],
+            ),
+            (
+                "2.10.0".to_owned(),
+                vec!["pydantic_core-2.10.0-cp312-cp312-wasi_0_0_0_wasm32.whl".to_owned()],
+            ),
+        ]);
+        let upstream = ["2.27.2", "2.28.0", "2.29.0b1", "2.10.0"].map(str::to_owned);
+
+        let recipe = Recipe::parse("pydantic-core", r#"backend = "maturin""#).unwrap();
+        let status = ProjectStatus::new(&recipe, upstream, Some(&published));
+
+        assert_eq!(status.latest_upstream.as_deref(), Some("2.28.0"));
+        assert_eq!(status.latest_published.as_deref(), Some("2.27.2"));
+        assert!(status.outdated);
+        assert_eq!(
+            status
+                .releases
+                .iter()
+                .map(|release| (
+                    release.version.as_str(),
+                    release.missing_python_versions.clone()
+                ))
+                .collect::<Vec<_>>(),
+            [("2.10.0", vec!["3.13".to_owned()]), ("2.27.2", vec![])]
+        );
+        assert!(status.to_string().contains("2.10.0 is missing Python 3.13"));
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["outdated"], true);
+        assert_eq!(json["latest-upstream"], "2.28.0");
+        assert_eq!(json["releases"][0]["missing-python-versions"][0], "3.13");
+    }
+
+    #[test]
+    fn only_compare_supported_versions() {
+        let recipe = Recipe::parse(
+            "regex",
+            r#"
+            backend = "setuptools"
+            versions = "<2025"
+            "#,
+        )
+        .unwrap();
+        let published = HashMap::from([(
+            "2024.11.6".to_owned(),
+            vec!["regex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl".to_owned()],
+        )]);
+        let upstream = ["2024.11.6", "2025.9.1"].map(str::to_owned);
+
+        let status = ProjectStatus::new(&recipe, upstream, Some(&published));
+
+        assert_eq!(status.latest_upstream.as_deref(), Some("2024.11.6"));
+        assert!(!status.outdated);
+    }
+
+    #[test]
+    fn pure_python_wheels_cover_all_versions() {
+        assert!(missing_python_versions(&["example-1.0.0-py3-none-any.whl".to_owned()]).is_empty());
+        assert_eq!(missing_python_versions(&[]), ["3.12", "3.13"]);
+    }
+
+    #[test]
+    fn nothing_published() {
+        let recipe = Recipe::parse("regex", r#"backend = "setuptools""#).unwrap();
+        let status = ProjectStatus::new(&recipe, ["2024.11.6".to_owned()], None);
+
+        assert!(status.outdated);
+        assert!(status.latest_published.is_none());
+        assert!(status.releases.is_empty());
+    }
+}
@@ -1,60 +1,61 @@
 //! Generate a custom index for WASI wheels.
-use std::{
-    collections::HashMap,
-    path::Path,
-    sync::{Arc, LazyLock},
-};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use askama::Template;
 use futures_util::TryStreamExt;
 use itertools::Itertools;
 use octocrab::{Octocrab, models::repos::Asset};
-use regex::Regex;
 use reqwest::Client;
 use tokio::{fs, pin, task::JoinSet};
 use url::Url;
 
+use crate::{MANIFEST_FILE, ReleaseManifest, publish::parse_body_hashes};
+
 pub struct Packages {
     packages: HashMap<String, HashMap<String, WheelFile>>,
+    /// Wheel filenames of each published release, by package and version
+    releases: HashMap<String, HashMap<String, Vec<String>>>,
 }
 
 impl Packages {
     fn new() -> Self {
         Self {
             packages: HashMap::new(),
+            releases: HashMap::new(),
         }
     }
 
-    fn parse_body_hashes(body: &str) -> Option<HashMap<String, String>> {
-        static RE: LazyLock<Regex> = LazyLock::new(|| {
-            Regex::new(r"(?s)### SHA256 Hashes\n\n```\n(?<hashes>.+)\n```").unwrap()
-        });
-        RE.captures(body).map(|cap| {
-            let hashes = &cap["hashes"];
-
-            hashes
-                .lines()
-                .filter_map(|line| {
-                    line.split_once('\t')
-                        .map(|(hash, wheel)| (wheel.to_owned(), hash.to_owned()))
-                })
-                .collect::<HashMap<_, _>>()
-        })
+    /// Wheel filenames of each published release of the given package, by version
+    pub fn releases(&self, package: &str) -> Option<&HashMap<String, Vec<String>>> {
+        self.releases.get(package)
     }
 
     async fn wheel_files(
         body: Option<String>,
         mut assets: Vec<Asset>,
     ) -> anyhow::Result<Vec<WheelFile>> {
-        // Pull hashes file out first so we can add them after
+        // Pull manifest and hashes file out first so we can add them after
+        let manifest = assets
+            .iter()
+            .position(|a| a.name == MANIFEST_FILE)
+            .map(|index| assets.remove(index));
         let hash_file = assets
             .iter()
             .position(|a| a.name == "hashes.txt")
             .map(|index| assets.remove(index));
 
-        // Use body if available, otherwise default to old hashfile approach
-        let mut hashes = if let Some(hashes) = body.and_then(|body| Self::parse_body_hashes(&body))
-        {
+        // Prefer the manifest, then the body, otherwise default to old hashfile approach
+        let mut hashes = if let Some(manifest) = manifest {
+            let client = Client::builder().use_rustls_tls().build().unwrap();
+            let manifest = client
+                .get(manifest.browser_download_url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            ReleaseManifest::parse(&manifest)?.hashes()
+        } else if let Some(hashes) = body.and_then(|body| parse_body_hashes(&body)) {
             hashes
         } else if let Some(hashes) = hash_file {
             let client = Client::builder().use_rustls_tls().build().unwrap();
@@ -96,7 +97,13 @@ impl Packages {
             .collect())
     }
 
-    fn extend(&mut self, package: &str, wheels: Vec<WheelFile>) {
+    fn extend(&mut self, package: &str, version: &str, wheels: Vec<WheelFile>) {
+        self.releases
+            .entry(package.to_owned())
+            .or_default()
+            .entry(version.to_owned())
+            .or_default()
+            .extend(wheels.iter().map(|wheel| wheel.name.clone()));
         let packages = self.packages.entry(package.to_owned()).or_default();
         packages.extend(wheels.into_iter().map(|a| (a.name.clone(), a)));
     }
@@ -205,21 +212,23 @@ impl GitHubReleaseClient {
         let mut set = JoinSet::<anyhow::Result<_>>::new();
 
         while let Some(release) = releases.try_next().await? {
-            let Some((package, _)) = release.tag_name.split_once("/v") else {
+            let Some((package, version)) = release.tag_name.split_once("/v") else {
                 continue;
             };
             let package = package.to_owned();
+            let version = version.to_owned();
             set.spawn(async move {
                 Ok((
                     package,
+                    version,
                     Packages::wheel_files(release.body, release.assets).await?,
                 ))
             });
         }
 
         while let Some(res) = set.join_next().await {
-            let (package, wheels) = res??;
-            packages.extend(&package, wheels);
+            let (package, version, wheels) = res??;
+            packages.extend(&package, &version, wheels);
         }
 
         Ok(packages)
@@ -1,11 +1,13 @@
 //! Tooling to generate Python wheels usable in WASI contexts and consumable as a Python registry.
 
-use std::path::PathBuf;
+use std::{path::PathBuf, process};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand};
 use wasi_wheels::{
-    PythonVersion, SupportedProjects, build_and_publish, download_package, generate_index,
-    install_build_tools,
+    Backend, BuildOptions, CleanScope, Plan, PublishOptions, PublishTarget, PythonVersion, Recipes,
+    RunError, SdistSource, Source, build_and_publish, build_generic, build_range, clean,
+    download_package, generate_index, install_build_tools, outdated, split_repo, verify_provenance,
+    verify_reproducible,
 };
 
 #[derive(Debug, Parser)]
@@ -13,43 +15,144 @@ use wasi_wheels::{
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Directory to load build recipes from. Defaults to "recipes" directory in this repository
+    #[arg(long, global = true)]
+    recipes_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// Prepares the necessary Cpython and WASI SDK tooling for building the tools.
-    InstallBuildTools,
+    InstallBuildTools {
+        /// Show what would be done instead of doing it
+        #[command(flatten)]
+        plan_flags: PlanFlags,
+    },
     /// Download the sdist package for the specified project and version
     DownloadPackage {
         /// The project (package) you want to download
         project: String,
         /// Which released version you want to download
-        release_version: String,
+        #[arg(required_unless_present = "sdist")]
+        release_version: Option<String>,
+        /// Unpack a local sdist archive instead of downloading one
+        #[arg(long, conflicts_with = "release_version")]
+        sdist: Option<PathBuf>,
         /// Where to download. Defaults to "packages" directory in current directory
         #[arg(short, long)]
         output_dir: Option<PathBuf>,
     },
     /// Build a given package into a WASI wheel.
+    #[command(group(
+        ArgGroup::new("publish_target")
+            .args(["repo", "run_id", "api_url", "publish_to", "signing_key"])
+            .multiple(true)
+            .requires("publish")
+    ))]
     Build {
-        /// The project (package) you want to download
-        project: SupportedProjects,
-        /// Which released version you want to download
-        release_version: String,
-        /// Where to download. Defaults to "packages" directory in current directory
-        #[arg(short, long)]
-        output_dir: Option<PathBuf>,
-        /// Optionally publish the wheel as a release in GitHub
-        #[arg(long)]
+        /// The project (package) you want to build. Must have a recipe
+        project: String,
+        /// Where to get the source to build from
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Where and how to build
+        #[command(flatten)]
+        build_flags: BuildFlags,
+        /// Optionally publish the wheel, as a release in GitHub by default
+        #[arg(long, conflicts_with = "dry_run")]
         publish: bool,
-        /// Optionally publish the wheel as a release in GitHub
+        /// Where and how to publish the wheel
         #[command(flatten)]
         publish_flags: PublishFlags,
-        /// Python versions to build with. Defaults to all supported versions
-        #[arg(long, value_enum, default_values_t=[PythonVersion::Py3_12, PythonVersion::Py3_13])]
-        python_versions: Vec<PythonVersion>,
-        /// Replace existing release if it exists
+        /// Show what would be done instead of doing it
+        #[command(flatten)]
+        plan_flags: PlanFlags,
+        /// Replace the release if it already exists, instead of only uploading missing or changed wheels
         #[arg(long)]
         replace_existing_release: bool,
+        /// Build even if the version is outside of the recipe's supported versions
+        #[arg(long)]
+        force: bool,
+        /// Build twice in separate directories, and fail if the wheels aren't identical
+        #[arg(long, conflicts_with_all = ["publish", "dry_run"])]
+        verify_reproducible: bool,
+    },
+    /// Build every released version of a project since a given version that isn't published yet.
+    /// Prints a summary of each build at the end.
+    ///
+    /// Published versions are read from the publish target, even without --publish.
+    #[command(group(
+        ArgGroup::new("publish_details")
+            .args(["run_id", "signing_key"])
+            .multiple(true)
+            .requires("publish")
+    ))]
+    BuildRange {
+        /// The project (package) you want to build. Must have a recipe
+        project: String,
+        /// Oldest version to build
+        #[arg(long)]
+        since: String,
+        /// Where and how to build
+        #[command(flatten)]
+        build_flags: BuildFlags,
+        /// Optionally publish each version, as a release in GitHub by default
+        #[arg(long)]
+        publish: bool,
+        /// Where to check for published versions, and how to publish
+        #[command(flatten)]
+        publish_flags: PublishFlags,
+        /// Keep building the remaining versions if one fails, instead of stopping
+        #[arg(long)]
+        keep_going: bool,
+    },
+    /// Build any project with a generic backend pipeline, without needing a recipe.
+    /// Reports whether the wheel contains WebAssembly extension modules or is pure Python.
+    BuildGeneric {
+        /// The project (package) you want to build
+        project: String,
+        /// Which backend to build the project with
+        #[arg(long, value_enum, default_value_t = Backend::Setuptools)]
+        backend: Backend,
+        /// Which released version you want to build
+        release_version: String,
+        /// Where and how to build
+        #[command(flatten)]
+        build_flags: BuildFlags,
+    },
+    /// Compare the upstream releases of every project with a recipe with the published wheels
+    Outdated {
+        /// Which repository the wheels are published in: <user>/<repo>
+        repo: String,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete toolchains, unpacked packages, virtual environments or wheels from previous builds
+    Clean {
+        /// What to delete. Defaults to everything
+        #[arg(value_enum, conflicts_with = "gc")]
+        scopes: Vec<CleanScope>,
+        /// Only delete `CPython` and WASI SDK directories that no supported Python version uses
+        #[arg(long)]
+        gc: bool,
+        /// List what would be deleted and how big it is, without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Where packages were downloaded. Defaults to "packages" directory in current directory
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+    },
+    /// Check a wheel against the signed in-toto provenance statement published next to it
+    VerifyProvenance {
+        /// The wheel to check
+        wheel: PathBuf,
+        /// Provenance file published with the wheel: `<project>-<version>.intoto.jsonl`
+        #[arg(long)]
+        provenance: PathBuf,
+        /// ed25519 public key, in a PEM file, the statement must be signed with
+        #[arg(long)]
+        public_key: PathBuf,
     },
     /// Generate a Python Package Index for a given repo
     GenerateIndex {
@@ -62,7 +165,97 @@ enum Commands {
 }
 
 #[derive(Args, Debug)]
-#[group(requires = "publish")]
+struct BuildFlags {
+    /// Where to download. Defaults to "packages" directory in current directory
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+    /// Python versions to build with. Defaults to all supported versions
+    #[arg(long, value_enum, default_values_t=[PythonVersion::Py3_12, PythonVersion::Py3_13])]
+    python_versions: Vec<PythonVersion>,
+    /// How many Python versions to build at once. Defaults to all of them
+    #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+    /// Build every wheel again, even if a previous run built it from the same inputs
+    #[arg(long)]
+    rebuild: bool,
+}
+
+impl From<BuildFlags> for BuildOptions {
+    fn from(flags: BuildFlags) -> Self {
+        Self {
+            output_dir: flags.output_dir,
+            python_versions: flags.python_versions,
+            jobs: flags.jobs.map(usize::from),
+            rebuild: flags.rebuild,
+            force: false,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct SourceArgs {
+    /// Which released version you want to download
+    #[arg(required_unless_present_any = ["sdist", "source_dir", "git"])]
+    release_version: Option<String>,
+    /// Build from a local sdist archive instead of downloading one.
+    /// The version is taken from its PKG-INFO.
+    #[arg(long, conflicts_with_all = ["release_version", "source_dir", "git", "publish"])]
+    sdist: Option<PathBuf>,
+    /// Build from a local source directory instead of a released sdist.
+    /// Wheels get a `+local` version label.
+    #[arg(long, conflicts_with_all = ["release_version", "git", "publish"])]
+    source_dir: Option<PathBuf>,
+    /// Build from a git repository instead of a released sdist.
+    /// Wheels get a `+g<commit>` version label.
+    #[arg(long, requires = "rev", conflicts_with_all = ["release_version", "publish"])]
+    git: Option<String>,
+    /// Branch, tag or commit to build from the git repository
+    #[arg(long, requires = "git")]
+    rev: Option<String>,
+}
+
+impl SourceArgs {
+    /// Pick the source to build from
+    fn source(self) -> Source {
+        let git = self.git.zip(self.rev);
+        match (self.release_version, self.sdist, self.source_dir, git) {
+            (Some(release_version), _, _, _) => Source::Release(release_version),
+            (None, Some(sdist), _, _) => Source::Sdist(sdist),
+            (None, None, Some(dir), _) => Source::Dir(dir),
+            (None, None, None, Some((url, rev))) => Source::Git { url, rev },
+            (None, None, None, None) => unreachable!("clap requires a source"),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct PlanFlags {
+    /// Print the downloads, directories and commands that would run, without running anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Print the plan as JSON
+    #[arg(long, requires = "dry_run")]
+    json: bool,
+}
+
+impl PlanFlags {
+    /// Run the future, or print what it would do when doing a dry run
+    async fn run(self, f: impl Future<Output = anyhow::Result<()>>) -> anyhow::Result<()> {
+        if !self.dry_run {
+            return f.await;
+        }
+
+        let plan = Plan::record(f).await?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&plan)?);
+        } else {
+            print!("{plan}");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
 struct PublishFlags {
     /// Which repository this is being released for: <user>/<repo>
     #[arg(long)]
@@ -70,9 +263,35 @@ struct PublishFlags {
     /// Which run id was used when building in CI
     #[arg(long)]
     run_id: Option<usize>,
+    /// Base URL of the GitHub API to publish to. Defaults to `GITHUB_API_URL`, or the public API
+    #[arg(long)]
+    api_url: Option<String>,
+    /// Where to publish: "github", "s3://<bucket>/<prefix>", the upload URL of a package index or a
+    /// local directory, as "dir:<path>" or "./<path>"
+    #[arg(long, default_value = "github")]
+    publish_to: String,
+    /// ed25519 private key, in a PKCS#8 PEM file, to sign an in-toto provenance statement with.
+    /// The statement is published next to the wheels
+    #[arg(long)]
+    signing_key: Option<PathBuf>,
 }
 
 impl PublishFlags {
+    /// Where and how to publish
+    fn options(self, replace_existing_release: bool) -> anyhow::Result<PublishOptions> {
+        Ok(PublishOptions {
+            notes: self.run_info(),
+            target: self.target()?,
+            replace_existing_release,
+            signing_key: self.signing_key,
+        })
+    }
+
+    /// Where to publish
+    fn target(&self) -> anyhow::Result<PublishTarget> {
+        PublishTarget::parse(&self.publish_to, self.repo.clone(), self.api_url.clone())
+    }
+
     /// Generate info about the run context
     fn run_info(&self) -> String {
         match (self.repo.as_ref(), self.run_id) {
@@ -89,39 +308,129 @@ impl PublishFlags {
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
+    // Dropping the running command on Ctrl-C also kills any processes it started
+    let result = tokio::select! {
+        result = execute(cli) => result,
+        _ = tokio::signal::ctrl_c() => Err(RunError::Cancelled.into()),
+    };
+    if result
+        .as_ref()
+        .is_err_and(|error| error.downcast_ref() == Some(&RunError::Cancelled))
+    {
+        eprintln!("Cancelled");
+        process::exit(130);
+    }
+    result
+}
+
+/// Print which projects have releases that aren't published yet
+async fn print_outdated(
+    recipes_dir: Option<PathBuf>,
+    repo: &str,
+    json: bool,
+) -> anyhow::Result<()> {
+    let recipes = Recipes::load(recipes_dir).await?;
+    let (owner, repo) = split_repo(repo)?;
+    let statuses = outdated(owner, repo, &recipes).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+    } else {
+        for status in statuses {
+            println!("{status}");
+        }
+    }
+    Ok(())
+}
+
+async fn execute(cli: Cli) -> anyhow::Result<()> {
     match cli.command {
-        Commands::InstallBuildTools => install_build_tools().await,
+        Commands::InstallBuildTools { plan_flags } => plan_flags.run(install_build_tools()).await,
         Commands::DownloadPackage {
             project,
             release_version,
+            sdist,
             output_dir,
         } => {
-            download_package(&project, &release_version, output_dir).await?;
+            let sdist = match (&release_version, &sdist) {
+                (_, Some(sdist)) => SdistSource::Archive(sdist),
+                (Some(release_version), None) => SdistSource::Release(release_version),
+                (None, None) => unreachable!("clap requires a version or sdist"),
+            };
+            download_package(&project, sdist, output_dir).await?;
             Ok(())
         }
         Commands::Build {
             project,
-            release_version,
-            output_dir,
+            source,
+            build_flags,
             publish,
-            python_versions,
             publish_flags,
             replace_existing_release,
+            force,
+            plan_flags,
+            verify_reproducible: reproducible,
         } => {
-            build_and_publish(
-                project,
-                &release_version,
-                output_dir,
-                &python_versions,
-                publish.then(|| publish_flags.run_info()),
-                replace_existing_release,
+            let recipes = Recipes::load(cli.recipes_dir).await?;
+            let recipe = recipes.get(&project)?;
+            let source = source.source();
+            let options = BuildOptions {
+                force,
+                ..build_flags.into()
+            };
+            if reproducible {
+                return verify_reproducible(recipe, &source, &options).await;
+            }
+            let publish = publish
+                .then(|| publish_flags.options(replace_existing_release))
+                .transpose()?;
+            plan_flags
+                .run(build_and_publish(
+                    recipe,
+                    &source,
+                    &options,
+                    publish.as_ref(),
+                ))
+                .await
+        }
+        Commands::BuildRange {
+            project,
+            since,
+            build_flags,
+            publish,
+            publish_flags,
+            keep_going,
+        } => {
+            let recipes = Recipes::load(cli.recipes_dir).await?;
+            build_range(
+                recipes.get(&project)?,
+                &since,
+                &publish_flags.target()?,
+                &build_flags.into(),
+                publish.then(|| publish_flags.options(false)).transpose()?,
+                keep_going,
             )
             .await
         }
+        Commands::BuildGeneric {
+            project,
+            backend,
+            release_version,
+            build_flags,
+        } => build_generic(&project, backend, &release_version, &build_flags.into()).await,
+        Commands::Outdated { repo, json } => print_outdated(cli.recipes_dir, &repo, json).await,
+        Commands::Clean {
+            scopes,
+            gc,
+            dry_run,
+            output_dir,
+        } => clean(&scopes, gc, output_dir, dry_run).await,
+        Commands::VerifyProvenance {
+            wheel,
+            provenance,
+            public_key,
+        } => verify_provenance(&wheel, &provenance, &public_key).await,
         Commands::GenerateIndex { repo, output_dir } => {
-            let (owner, repo) = repo
-                .split_once('/')
-                .ok_or_else(|| anyhow::anyhow!("Invalid repo argument of type <owner>/<repo>"))?;
+            let (owner, repo) = split_repo(&repo)?;
             generate_index(owner, repo, output_dir).await
         }
     }
@@ -0,0 +1,302 @@
+//! Plan builds and toolchain installs without running them. While planning, downloads, changes
+//! to the filesystem and commands are recorded instead of carried out.
+use std::{
+    collections::BTreeMap,
+    fmt, future, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{FutureExt, future::BoxFuture};
+use serde::Serialize;
+use tokio::{fs, process::Command};
+
+use crate::runner::{CommandRunner, with_runner};
+
+tokio::task_local! {
+    /// Plan being recorded on the current task, if any
+    static PLAN: Plan;
+}
+
+/// Everything a build or install would do, in order
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    /// Actions recorded so far
+    actions: Arc<Mutex<Vec<Action>>>,
+}
+
+/// A single step of a plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    /// Download an archive and unpack it
+    Download {
+        /// Where the archive is downloaded from
+        url: String,
+        /// Where it is unpacked to
+        to: PathBuf,
+    },
+    /// Create a directory and any missing parents
+    CreateDir {
+        /// The directory to create
+        path: PathBuf,
+    },
+    /// Delete a directory and everything in it
+    RemoveDir {
+        /// The directory to delete
+        path: PathBuf,
+    },
+    /// Move a file or directory
+    Rename {
+        /// Current path
+        from: PathBuf,
+        /// New path
+        to: PathBuf,
+    },
+    /// Write a file
+    Write {
+        /// The file to write
+        path: PathBuf,
+    },
+    /// Run a command
+    Run {
+        /// The full command line
+        command: String,
+        /// Directory the command runs in, if not the current one
+        current_dir: Option<PathBuf>,
+        /// Environment variables set for the command
+        env: BTreeMap<String, String>,
+    },
+}
+
+impl Plan {
+    /// Record what the future would do, instead of doing it
+    ///
+    /// # Errors
+    /// If the future fails, i.e. because information needed for the plan can't be retrieved.
+    pub async fn record<T>(f: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<Self> {
+        let plan = Self::default();
+        PLAN.scope(plan.clone(), with_runner(Arc::new(plan.clone()), f))
+            .await?;
+        Ok(plan)
+    }
+
+    /// Actions recorded so far
+    ///
+    /// # Panics
+    /// If recording an action panicked.
+    #[must_use]
+    pub fn actions(&self) -> Vec<Action> {
+        self.actions.lock().unwrap().clone()
+    }
+}
+
+impl CommandRunner for Plan {
+    fn run<'a>(
+        &'a self,
+        command: &'a mut Command,
+        command_string: &'a str,
+        _timeout: Option<Duration>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.actions
+            .lock()
+            .unwrap()
+            .push(Action::run(command, command_string));
+        future::ready(Ok(())).boxed()
+    }
+}
+
+impl Serialize for Plan {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.actions().serialize(serializer)
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let actions = self.actions();
+        if actions.is_empty() {
+            return writeln!(f, "Nothing to do");
+        }
+        for (number, action) in actions.iter().enumerate() {
+            writeln!(f, "{:>3}. {action}", number + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Download { url, to } => write!(f, "Download {url}\n     to {}", to.display()),
+            Self::CreateDir { path } => write!(f, "Create directory {}", path.display()),
+            Self::RemoveDir { path } => write!(f, "Remove directory {}", path.display()),
+            Self::Rename { from, to } => {
+                write!(f, "Move {}\n     to {}", from.display(), to.display())
+            }
+            Self::Write { path } => write!(f, "Write {}", path.display()),
+            Self::Run {
+                command,
+                current_dir,
+                env,
+            } => {
+                write!(f, "Run `{command}`")?;
+                if let Some(current_dir) = current_dir {
+                    write!(f, "\n     in {}", current_dir.display())?;
+                }
+                for (key, value) in env {
+                    write!(f, "\n     {key}={value}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Action {
+    /// Running the given command
+    pub(crate) fn run(command: &Command, command_string: &str) -> Self {
+        let command = command.as_std();
+        Self::Run {
+            command: command_string.to_owned(),
+            current_dir: command.get_current_dir().map(ToOwned::to_owned),
+            env: command
+                .get_envs()
+                .filter_map(|(key, value)| {
+                    Some((
+                        key.to_string_lossy().into_owned(),
+                        value?.to_string_lossy().into_owned(),
+                    ))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Whether actions are being planned instead of carried out
+pub(crate) fn is_planning() -> bool {
+    PLAN.try_with(|_| ()).is_ok()
+}
+
+/// Record the action if planning. Returns whether it was recorded, in which case the caller
+/// should skip it.
+pub(crate) fn record(action: Action) -> bool {
+    PLAN.try_with(|plan| plan.actions.lock().unwrap().push(action))
+        .is_ok()
+}
+
+/// Whether the path exists, or would by now when planning
+pub(crate) fn exists(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    path.exists()
+        || PLAN
+            .try_with(|plan| {
+                plan.actions
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|action| match action {
+                        Action::Download { to, .. } | Action::Rename { to, .. } => to == path,
+                        Action::CreateDir { path: created } | Action::Write { path: created } => {
+                            created == path
+                        }
+                        Action::RemoveDir { .. } | Action::Run { .. } => false,
+                    })
+            })
+            .unwrap_or_default()
+}
+
+/// Create a directory and any missing parents, unless planning
+pub(crate) async fn create_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    if record(Action::CreateDir {
+        path: path.to_owned(),
+    }) {
+        return Ok(());
+    }
+    fs::create_dir_all(path).await
+}
+
+/// Delete a directory and everything in it, unless planning
+pub(crate) async fn remove_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    if record(Action::RemoveDir {
+        path: path.to_owned(),
+    }) {
+        return Ok(());
+    }
+    fs::remove_dir_all(path).await
+}
+
+/// Move a file or directory, unless planning
+pub(crate) async fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if record(Action::Rename {
+        from: from.to_owned(),
+        to: to.to_owned(),
+    }) {
+        return Ok(());
+    }
+    fs::rename(from, to).await
+}
+
+/// Write a file, unless planning
+pub(crate) async fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref();
+    if record(Action::Write {
+        path: path.to_owned(),
+    }) {
+        return Ok(());
+    }
+    fs::write(path, contents).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn record_instead_of_running() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path().join("created");
+
+        let plan = Plan::record(async {
+            create_dir_all(&dir).await?;
+            run(Command::new("touch")
+                .arg("file")
+                .current_dir(&dir)
+                .env("FOO", "bar"))
+            .await
+        })
+        .await?;
+
+        assert!(!dir.exists());
+        assert_eq!(
+            plan.actions(),
+            [
+                Action::CreateDir { path: dir.clone() },
+                Action::Run {
+                    command: "touch file".to_owned(),
+                    current_dir: Some(dir.clone()),
+                    env: BTreeMap::from([("FOO".to_owned(), "bar".to_owned())]),
+                },
+            ]
+        );
+        assert_eq!(
+            plan.to_string(),
+            format!(
+                "  1. Create directory {0}\n  2. Run `touch file`\n     in {0}\n     FOO=bar\n",
+                dir.display()
+            )
+        );
+        assert_eq!(
+            serde_json::to_value(&plan)?[1]["action"],
+            serde_json::json!("run")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+//! In-toto attestations describing how wheels were built, signed with an ed25519 key.
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ed25519_dalek::{
+    Signature, Signer, SigningKey, Verifier, VerifyingKey,
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::ReleaseManifest;
+
+/// Extension of the provenance file published next to the wheels of a release
+pub const PROVENANCE_EXTENSION: &str = "intoto.jsonl";
+
+/// Type of an in-toto statement
+const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+/// Type of the predicate describing the build
+const PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v1";
+/// Type of the build described by the predicate
+const BUILD_TYPE: &str = "https://github.com/benbrandt/wasi-wheels/build/v1";
+/// Payload type of a DSSE envelope containing an in-toto statement
+const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+/// An in-toto statement about the wheels of a build
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Statement {
+    #[serde(rename = "_type")]
+    kind: String,
+    /// The wheels the statement is about
+    subject: Vec<Subject>,
+    #[serde(rename = "predicateType")]
+    predicate_type: String,
+    /// How the wheels were built
+    predicate: Value,
+}
+
+/// A wheel and its digests
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Subject {
+    name: String,
+    /// Digests by algorithm, i.e. `sha256`
+    digest: BTreeMap<String, String>,
+}
+
+/// A DSSE envelope with a signed statement
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Envelope {
+    payload_type: String,
+    /// Base64 encoded statement
+    payload: String,
+    signatures: Vec<EnvelopeSignature>,
+}
+
+/// A signature of an envelope's payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct EnvelopeSignature {
+    /// Sha256 of the public key that made the signature
+    keyid: String,
+    /// Base64 encoded signature
+    sig: String,
+}
+
+/// Everything a build's provenance describes
+#[derive(Debug)]
+pub(crate) struct BuildProvenance<'a> {
+    /// Wheels that were built, and what they were built with
+    pub manifest: &'a ReleaseManifest,
+    /// URL of the sdist the wheels were built from, if any
+    pub sdist_url: Option<&'a str>,
+    /// Sha256 of the sdist the wheels were built from, if any
+    pub sdist_sha256: Option<&'a str>,
+    /// Every command that ran during the build
+    pub commands: &'a [String],
+    /// Which run built the wheels
+    pub run_info: &'a str,
+}
+
+impl BuildProvenance<'_> {
+    /// Statement with the wheels as subjects and the build as predicate
+    fn statement(&self) -> Statement {
+        let subject = self
+            .manifest
+            .wheels
+            .iter()
+            .map(|wheel| Subject {
+                name: wheel.filename.clone(),
+                digest: BTreeMap::from([("sha256".to_owned(), wheel.sha256.clone())]),
+            })
+            .collect();
+        let toolchains = self
+            .manifest
+            .wheels
+            .iter()
+            .map(|wheel| {
+                json!({
+                    "wheel": wheel.filename,
+                    "python-version": wheel.python_version,
+                    "cpython-version": wheel.cpython_version,
+                    "wasi-sdk-version": wheel.wasi_sdk_version,
+                    "build-flags": wheel.build_flags,
+                })
+            })
+            .collect::<Vec<_>>();
+        let resolved_dependencies = match self.sdist_url {
+            Some(uri) => json!([{
+                "uri": uri,
+                "digest": self.sdist_sha256.map(|sha256| json!({ "sha256": sha256 })),
+            }]),
+            None => json!([]),
+        };
+
+        Statement {
+            kind: STATEMENT_TYPE.to_owned(),
+            subject,
+            predicate_type: PREDICATE_TYPE.to_owned(),
+            predicate: json!({
+                "buildDefinition": {
+                    "buildType": BUILD_TYPE,
+                    "externalParameters": {
+                        "project": self.manifest.project,
+                        "version": self.manifest.version,
+                        "sdist": self.sdist_url,
+                    },
+                    "internalParameters": {
+                        "toolchains": toolchains,
+                        "commands": self.commands,
+                    },
+                    "resolvedDependencies": resolved_dependencies,
+                },
+                "runDetails": {
+                    "builder": {
+                        "id": BUILD_TYPE,
+                        "version": { "wasi-wheels": env!("CARGO_PKG_VERSION") },
+                    },
+                    "metadata": { "runInfo": self.run_info },
+                },
+            }),
+        }
+    }
+
+    /// Sign the statement with the key in the PKCS#8 PEM file, and write the envelope to
+    /// `<project>-<version>.intoto.jsonl` in the directory
+    pub async fn write(&self, signing_key: &Path, dir: &Path) -> anyhow::Result<PathBuf> {
+        let pem = fs::read_to_string(signing_key)
+            .await
+            .with_context(|| format!("Unable to read signing key {}", signing_key.display()))?;
+        let key = SigningKey::from_pkcs8_pem(&pem)
+            .with_context(|| format!("{} isn't an ed25519 private key", signing_key.display()))?;
+
+        let path = dir.join(format!(
+            "{}-{}.{PROVENANCE_EXTENSION}",
+            self.manifest.project, self.manifest.version
+        ));
+        let mut line = serde_json::to_string(&sign(&self.statement(), &key)?)?;
+        line.push('\n');
+        fs::write(&path, line).await?;
+
+        Ok(path)
+    }
+}
+
+/// Pre-authentication encoding of a DSSE payload, which is what gets signed
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut encoded = format!(
+        "DSSEv1 {} {payload_type} {} ",
+        payload_type.len(),
+        payload.len()
+    )
+    .into_bytes();
+    encoded.extend_from_slice(payload);
+    encoded
+}
+
+/// Identifier of a public key: the sha256 of its bytes
+fn key_id(key: &VerifyingKey) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Sign a statement, wrapping it in a DSSE envelope
+fn sign(statement: &Statement, key: &SigningKey) -> anyhow::Result<Envelope> {
+    let payload = serde_json::to_vec(statement)?;
+    let signature = key.sign(&pae(PAYLOAD_TYPE, &payload));
+    Ok(Envelope {
+        payload_type: PAYLOAD_TYPE.to_owned(),
+        payload: STANDARD.encode(&payload),
+        signatures: vec![EnvelopeSignature {
+            keyid: key_id(&key.verifying_key()),
+            sig: STANDARD.encode(signature.to_bytes()),
+        }],
+    })
+}
+
+/// The statement in an envelope, if the key signed it
+fn verify(envelope: &Envelope, key: &VerifyingKey) -> anyhow::Result<Statement> {
+    if envelope.payload_type != PAYLOAD_TYPE {
+        bail!("Unexpected payload type {}", envelope.payload_type);
+    }
+    let payload = STANDARD.decode(&envelope.payload)?;
+    let message = pae(&envelope.payload_type, &payload);
+    let signed = envelope.signatures.iter().any(|signature| {
+        STANDARD
+            .decode(&signature.sig)
+            .ok()
+            .and_then(|sig| Signature::from_slice(&sig).ok())
+            .is_some_and(|sig| key.verify(&message, &sig).is_ok())
+    });
+    if !signed {
+        bail!("No valid signature from key {}", key_id(key));
+    }
+
+    let statement: Statement = serde_json::from_slice(&payload)?;
+    if statement.kind != STATEMENT_TYPE {
+        bail!("Unexpected statement type {}", statement.kind);
+    }
+    Ok(statement)
+}
+
+/// Check a wheel against a provenance file: one of its statements must be signed by the public
+/// key, in a PEM file, and list the wheel with its sha256 as a subject. Other statements, such as
+/// those of earlier publishes or signed by other keys, are skipped.
+///
+/// # Errors
+/// If the files can't be read, or the wheel doesn't match a signed statement.
+pub async fn verify_provenance(
+    wheel: &Path,
+    provenance: &Path,
+    public_key: &Path,
+) -> anyhow::Result<()> {
+    let pem = fs::read_to_string(public_key)
+        .await
+        .with_context(|| format!("Unable to read public key {}", public_key.display()))?;
+    let key = VerifyingKey::from_public_key_pem(&pem)
+        .with_context(|| format!("{} isn't an ed25519 public key", public_key.display()))?;
+    let name = wheel
+        .file_name()
+        .with_context(|| format!("Invalid wheel path {}", wheel.display()))?
+        .to_string_lossy();
+    let sha256 = format!("{:x}", Sha256::digest(fs::read(wheel).await?));
+
+    let envelopes = fs::read_to_string(provenance)
+        .await
+        .with_context(|| format!("Unable to read {}", provenance.display()))?;
+    // Each publish appends an envelope, which may be signed by another key or list an earlier
+    // build of the wheel
+    let mut listed = false;
+    for line in envelopes.lines().filter(|line| !line.trim().is_empty()) {
+        let Ok(statement) = serde_json::from_str(line)
+            .map_err(anyhow::Error::from)
+            .and_then(|envelope| verify(&envelope, &key))
+        else {
+            continue;
+        };
+        let Some(subject) = statement.subject.iter().find(|s| s.name == name) else {
+            continue;
+        };
+        if subject.digest.get("sha256") == Some(&sha256) {
+            println!("{name} matches its provenance, signed by {}", key_id(&key));
+            return Ok(());
+        }
+        listed = true;
+    }
+
+    if listed {
+        bail!("{name} has sha256 {sha256}, but its provenance lists a different one");
+    }
+    bail!(
+        "{name} isn't a subject of a statement in {} signed by key {}",
+        provenance.display(),
+        key_id(&key)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::pkcs8::{EncodePrivateKey, EncodePublicKey, spki::der::pem::LineEnding};
+
+    use super::*;
+    use crate::WheelManifest;
+
+    fn manifest(sha256: &str) -> ReleaseManifest {
+        ReleaseManifest {
+            project: "regex".to_owned(),
+            version: "2024.11.6".to_owned(),
+            wheels: vec![WheelManifest {
+                filename: "regex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl".to_owned(),
+                sha256: sha256.to_owned(),
+                size: 5,
+                python_version: Some("3.12".to_owned()),
+                cpython_version: Some("3.12.9".to_owned()),
+                wasi_sdk_version: Some("24".to_owned()),
+                wasi_wheels_version: "0.1.0".to_owned(),
+                build_flags: json!({ "backend": "setuptools" }),
+            }],
+        }
+    }
+
+    #[test]
+    fn describe_build_in_statement() {
+        let manifest = manifest("abc");
+        let commands = ["python -m build".to_owned()];
+        let statement = BuildProvenance {
+            manifest: &manifest,
+            sdist_url: Some("https://files.example/regex-2024.11.6.tar.gz"),
+            sdist_sha256: Some("def"),
+            commands: &commands,
+            run_info: "Built with run 1",
+        }
+        .statement();
+
+        assert_eq!(statement.subject[0].digest["sha256"], "abc");
+        let definition = &statement.predicate["buildDefinition"];
+        assert_eq!(
+            definition["resolvedDependencies"][0]["digest"]["sha256"],
+            "def"
+        );
+        assert_eq!(
+            definition["internalParameters"]["toolchains"][0]["wasi-sdk-version"],
+            "24"
+        );
+        assert_eq!(
+            definition["internalParameters"]["commands"][0],
+            "python -m build"
+        );
+        assert_eq!(
+            statement.predicate["runDetails"]["metadata"]["runInfo"],
+            "Built with run 1"
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_and_verify_wheels() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        fs::write(
+            dir.join("key.pem"),
+            key.to_pkcs8_pem(LineEnding::LF)?.as_bytes(),
+        )
+        .await?;
+        fs::write(
+            dir.join("key.pub"),
+            key.verifying_key().to_public_key_pem(LineEnding::LF)?,
+        )
+        .await?;
+
+        let wheel = dir.join("regex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl");
+        fs::write(&wheel, "wheel").await?;
+        let manifest = manifest(&format!("{:x}", Sha256::digest("wheel")));
+        let provenance = BuildProvenance {
+            manifest: &manifest,
+            sdist_url: None,
+            sdist_sha256: None,
+            commands: &[],
+            run_info: "No provided run information",
+        }
+        .write(&dir.join("key.pem"), dir)
+        .await?;
+        assert_eq!(
+            provenance.file_name().unwrap(),
+            "regex-2024.11.6.intoto.jsonl"
+        );
+
+        verify_provenance(&wheel, &provenance, &dir.join("key.pub")).await?;
+
+        fs::write(&wheel, "tampered").await?;
+        assert!(
+            verify_provenance(&wheel, &provenance, &dir.join("key.pub"))
+                .await
+                .is_err()
+        );
+
+        let other = SigningKey::from_bytes(&[8; 32]);
+        fs::write(
+            dir.join("other.pub"),
+            other.verifying_key().to_public_key_pem(LineEnding::LF)?,
+        )
+        .await?;
+        fs::write(&wheel, "wheel").await?;
+        assert!(
+            verify_provenance(&wheel, &provenance, &dir.join("other.pub"))
+                .await
+                .is_err()
+        );
+
+        // Statements of earlier publishes, or signed by other keys, come first in the file
+        fs::write(
+            dir.join("other.pem"),
+            other.to_pkcs8_pem(LineEnding::LF)?.as_bytes(),
+        )
+        .await?;
+        let earlier = dir.join("earlier");
+        fs::create_dir_all(&earlier).await?;
+        let mut appended = vec![];
+        for (key, sha256) in [("other.pem", "abc"), ("key.pem", "def")] {
+            let listed = self::manifest(sha256);
+            let envelope = BuildProvenance {
+                manifest: &listed,
+                sdist_url: None,
+                sdist_sha256: None,
+                commands: &[],
+                run_info: "No provided run information",
+            }
+            .write(&dir.join(key), &earlier)
+            .await?;
+            appended.extend(fs::read(envelope).await?);
+        }
+        appended.extend(fs::read(&provenance).await?);
+        fs::write(&provenance, appended).await?;
+        verify_provenance(&wheel, &provenance, &dir.join("key.pub")).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+//! Publish built wheels.
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsStr,
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::{Context, bail};
+use futures_util::future::BoxFuture;
+use regex::Regex;
+
+mod directory;
+mod github;
+mod manifest;
+mod s3;
+mod upload;
+
+pub use directory::LocalDirectory;
+pub use github::{GitHubError, GitHubReleases, split_repo};
+pub use manifest::{MANIFEST_FILE, ReleaseManifest, WheelManifest};
+pub use s3::S3Bucket;
+pub use upload::PackageRepository;
+
+/// File listing the sha256 of each wheel, for publishers without release notes
+const HASHES_FILE: &str = "hashes.txt";
+
+/// Section of the release notes listing the sha256 of each wheel, read by the index generator
+static BODY_HASHES: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)### SHA256 Hashes\n\n```\n(?<hashes>.+)\n```").unwrap());
+
+/// Somewhere built wheels can be published to
+pub trait Publisher: Send + Sync {
+    /// Publish the wheels built for a release. `hashes` has a `<sha256>\t<filename>` line for
+    /// each wheel.
+    fn publish<'a>(
+        &'a self,
+        release: &'a Release,
+        wheel_paths: &'a [PathBuf],
+        hashes: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Versions of the project that already have a release
+    fn published_versions<'a>(
+        &'a self,
+        project: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<HashSet<String>>>;
+}
+
+/// A released version of a project to publish wheels for
+#[derive(Debug, Clone)]
+pub struct Release {
+    /// Project the wheels were built for
+    pub project: String,
+    /// Version the wheels were built from
+    pub version: String,
+    /// Notes to include in the release, i.e. which run built it
+    pub notes: String,
+    /// Replace the release if it already exists, instead of only uploading what changed
+    pub replace_existing_release: bool,
+    /// Manifest of the wheels, published alongside them
+    pub manifest: ReleaseManifest,
+    /// Other files to publish alongside the wheels, such as their provenance. They are appended to
+    /// published files with the same name, so the records of earlier publishes are kept.
+    pub attachments: Vec<PathBuf>,
+}
+
+impl Release {
+    /// Tag of the release: `<project>/v<version>`
+    #[must_use]
+    pub fn tag(&self) -> String {
+        format!("{}/v{}", self.project, self.version)
+    }
+}
+
+/// Where to publish built wheels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublishTarget {
+    /// Releases in a GitHub repository
+    GitHub {
+        /// Repository to create the release in: `<owner>/<repo>`
+        repo: String,
+        /// Base URL of the GitHub API. Defaults to `GITHUB_API_URL`, or the public GitHub API
+        api_url: Option<String>,
+    },
+    /// A local directory, with a `<project>/<version>` directory for each release
+    Directory(PathBuf),
+    /// An S3-compatible bucket: `s3://<bucket>/<prefix>`
+    S3(String),
+    /// A package index with the legacy upload API, i.e. pypiserver or devpi
+    Repository(String),
+}
+
+impl PublishTarget {
+    /// Parse a `--publish-to` argument: `github`, `s3://<bucket>/<prefix>`, the upload URL of a
+    /// package index or a directory. Directories are given as `dir:<path>`, a path starting with
+    /// `/`, `./` or `../`, or an existing directory, so a typo isn't taken for a new one.
+    /// Publishing to GitHub needs a repo.
+    ///
+    /// # Errors
+    /// If publishing to GitHub without a repo, or the target isn't any of these.
+    pub fn parse(
+        publish_to: &str,
+        repo: Option<String>,
+        api_url: Option<String>,
+    ) -> anyhow::Result<Self> {
+        Ok(match publish_to {
+            "github" => Self::GitHub {
+                repo: repo.context("Publishing to GitHub needs a --repo")?,
+                api_url,
+            },
+            url if url.starts_with("s3://") => Self::S3(url.to_owned()),
+            url if url.starts_with("http://") || url.starts_with("https://") => {
+                Self::Repository(url.to_owned())
+            }
+            target => match target.strip_prefix("dir:") {
+                Some(path) => Self::Directory(PathBuf::from(path)),
+                None if is_path(target) => Self::Directory(PathBuf::from(target)),
+                None => bail!(
+                    "Unknown publish target {target}: expected github, s3://<bucket>/<prefix>, \
+                     an http(s) upload URL or a directory, i.e. dir:{target} or ./{target}"
+                ),
+            },
+        })
+    }
+
+    /// Publisher for the target
+    ///
+    /// # Errors
+    /// If the target is invalid or its client can't be created.
+    pub fn publisher(&self) -> anyhow::Result<Box<dyn Publisher>> {
+        Ok(match self {
+            Self::GitHub { repo, api_url } => {
+                Box::new(GitHubReleases::new(repo, api_url.as_deref())?)
+            }
+            Self::Directory(path) => Box::new(LocalDirectory::new(path)),
+            Self::S3(url) => Box::new(S3Bucket::new(url)?),
+            Self::Repository(url) => Box::new(PackageRepository::new(url)?),
+        })
+    }
+}
+
+/// Where and how to publish built wheels
+#[derive(Debug, Clone)]
+pub struct PublishOptions {
+    /// Where to publish the wheels
+    pub target: PublishTarget,
+    /// Notes to include in the release, i.e. which run built it
+    pub notes: String,
+    /// Replace the release if it already exists, instead of only uploading what changed
+    pub replace_existing_release: bool,
+    /// ed25519 private key, in a PKCS#8 PEM file, to sign the provenance of the wheels with. No
+    /// provenance is published without one.
+    pub signing_key: Option<PathBuf>,
+}
+
+/// Whether a publish target is clearly meant as a directory
+fn is_path(target: &str) -> bool {
+    ["/", "./", "../"]
+        .iter()
+        .any(|prefix| target.starts_with(prefix))
+        || Path::new(target).is_dir()
+}
+
+/// Parse `<sha256>\t<filename>` lines into hashes by filename
+pub(crate) fn parse_hashes(hashes: &str) -> HashMap<String, String> {
+    hashes
+        .lines()
+        .filter_map(|line| {
+            line.split_once('\t')
+                .map(|(hash, wheel)| (wheel.to_owned(), hash.to_owned()))
+        })
+        .collect()
+}
+
+/// Format hashes by filename as `<sha256>\t<filename>` lines
+pub(crate) fn format_hashes(hashes: &BTreeMap<String, String>) -> String {
+    let mut lines = String::new();
+    for (filename, hash) in hashes {
+        writeln!(lines, "{hash}\t{filename}").unwrap();
+    }
+    lines
+}
+
+/// Add hashes to previously published `<sha256>\t<filename>` lines, replacing those of files
+/// published again
+pub(crate) fn merge_hashes(published: &str, hashes: &str) -> String {
+    let mut merged = parse_hashes(published)
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+    merged.extend(parse_hashes(hashes));
+    format_hashes(&merged)
+}
+
+/// Contents of an attachment appended to its previously published version, on a new line
+pub(crate) fn append_attachment(published: &[u8], contents: &[u8]) -> Vec<u8> {
+    let mut appended = published.to_vec();
+    if !appended.is_empty() && !appended.ends_with(b"\n") {
+        appended.push(b'\n');
+    }
+    appended.extend_from_slice(contents);
+    appended
+}
+
+/// Name of a file to publish
+pub(crate) fn file_name(path: &Path) -> anyhow::Result<&str> {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .with_context(|| format!("Invalid file path {}", path.display()))
+}
+
+/// Hashes by filename from the hashes section of release notes, if there is one
+pub(crate) fn parse_body_hashes(body: &str) -> Option<HashMap<String, String>> {
+    BODY_HASHES
+        .captures(body)
+        .map(|captures| parse_hashes(&captures["hashes"]))
+}
+
+/// Release notes with the hashes section replaced, or added if there isn't one yet
+pub(crate) fn replace_body_hashes(body: &str, hashes: &str) -> String {
+    let section = format!("### SHA256 Hashes\n\n```\n{hashes}```");
+    if BODY_HASHES.is_match(body) {
+        BODY_HASHES
+            .replace(body, regex::NoExpand(&section))
+            .into_owned()
+    } else if body.is_empty() {
+        section
+    } else {
+        format!("{body}\n\n{section}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regenerate_body_hashes() {
+        let hashes = BTreeMap::from([
+            ("b.whl".to_owned(), "2222".to_owned()),
+            ("a.whl".to_owned(), "1111".to_owned()),
+        ]);
+        let body = replace_body_hashes("Built with run 1", &format_hashes(&hashes));
+        assert_eq!(
+            body,
+            "Built with run 1\n\n### SHA256 Hashes\n\n```\n1111\ta.whl\n2222\tb.whl\n```"
+        );
+        assert_eq!(parse_body_hashes(&body), Some(hashes.into_iter().collect()));
+
+        let replaced = replace_body_hashes(&body, "3333\tc.whl\n");
+        assert_eq!(
+            replaced,
+            "Built with run 1\n\n### SHA256 Hashes\n\n```\n3333\tc.whl\n```"
+        );
+        assert_eq!(
+            parse_body_hashes(&replaced),
+            Some(HashMap::from([("c.whl".to_owned(), "3333".to_owned())]))
+        );
+    }
+
+    #[test]
+    fn parse_publish_targets() -> anyhow::Result<()> {
+        assert_eq!(
+            PublishTarget::parse("github", Some("owner/repo".to_owned()), None)?,
+            PublishTarget::GitHub {
+                repo: "owner/repo".to_owned(),
+                api_url: None
+            }
+        );
+        assert!(PublishTarget::parse("github", None, None).is_err());
+        assert_eq!(
+            PublishTarget::parse("s3://wheels/wasi", None, None)?,
+            PublishTarget::S3("s3://wheels/wasi".to_owned())
+        );
+        assert_eq!(
+            PublishTarget::parse("http://localhost:8080/", None, None)?,
+            PublishTarget::Repository("http://localhost:8080/".to_owned())
+        );
+        assert_eq!(
+            PublishTarget::parse("./wheels", None, None)?,
+            PublishTarget::Directory(PathBuf::from("./wheels"))
+        );
+        assert_eq!(
+            PublishTarget::parse("dir:wheels", None, None)?,
+            PublishTarget::Directory(PathBuf::from("wheels"))
+        );
+        assert_eq!(
+            PublishTarget::parse("src", None, None)?,
+            PublishTarget::Directory(PathBuf::from("src"))
+        );
+        // Typos aren't taken for a new directory
+        assert!(PublishTarget::parse("githb", Some("owner/repo".to_owned()), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn append_to_published_attachments() {
+        assert_eq!(append_attachment(b"", b"{}\n"), b"{}\n");
+        assert_eq!(append_attachment(b"[]\n", b"{}\n"), b"[]\n{}\n");
+        assert_eq!(append_attachment(b"[]", b"{}\n"), b"[]\n{}\n");
+    }
+
+    #[test]
+    fn merge_published_hashes() {
+        assert_eq!(
+            merge_hashes("1111\ta.whl\n2222\tb.whl\n", "3333\tb.whl\n4444\tc.whl\n"),
+            "1111\ta.whl\n3333\tb.whl\n4444\tc.whl\n"
+        );
+    }
+}
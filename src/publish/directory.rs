@@ -0,0 +1,192 @@
+//! Publish wheels to a local directory.
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use futures_util::{FutureExt, future::BoxFuture};
+use tokio::fs;
+
+use crate::publish::{
+    HASHES_FILE, MANIFEST_FILE, Publisher, Release, ReleaseManifest, append_attachment, file_name,
+    merge_hashes,
+};
+
+/// Copies wheels into a local directory, under `<project>/<version>` with a `hashes.txt` listing
+/// the hash of each wheel and a `manifest.json`
+#[derive(Debug, Clone)]
+pub struct LocalDirectory {
+    path: PathBuf,
+}
+
+impl LocalDirectory {
+    /// Publish to the given directory. It is created if it doesn't exist yet.
+    #[must_use]
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+        }
+    }
+
+    /// Copy the wheels into the release's directory, add them to the hashes file and manifest, and
+    /// append the attachments
+    async fn publish_release(
+        &self,
+        release: &Release,
+        wheel_paths: &[PathBuf],
+        hashes: &str,
+    ) -> anyhow::Result<()> {
+        let dir = self.path.join(&release.project).join(&release.version);
+        if release.replace_existing_release && dir.exists() {
+            fs::remove_dir_all(&dir).await?;
+        }
+        fs::create_dir_all(&dir).await?;
+
+        for path in wheel_paths {
+            fs::copy(path, dir.join(file_name(path)?)).await?;
+        }
+        for path in &release.attachments {
+            let published_path = dir.join(file_name(path)?);
+            let published = read_published(&published_path).await?.unwrap_or_default();
+            fs::write(
+                &published_path,
+                append_attachment(&published, &fs::read(path).await?),
+            )
+            .await?;
+        }
+
+        let hashes_path = dir.join(HASHES_FILE);
+        let published = read_published(&hashes_path).await?.unwrap_or_default();
+        fs::write(
+            &hashes_path,
+            merge_hashes(&String::from_utf8(published)?, hashes),
+        )
+        .await?;
+
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest = match read_published(&manifest_path).await? {
+            Some(published) => release.manifest.merge(&ReleaseManifest::parse(&published)?),
+            None => release.manifest.clone(),
+        };
+        fs::write(&manifest_path, manifest.to_json()?).await?;
+
+        println!("Published {} to {}", release.tag(), dir.display());
+        Ok(())
+    }
+
+    /// Names of the version directories of the project
+    async fn versions(&self, project: &str) -> anyhow::Result<HashSet<String>> {
+        let mut entries = match fs::read_dir(self.path.join(project)).await {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+            Err(error) => return Err(error.into()),
+        };
+        let mut versions = HashSet::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                versions.insert(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(versions)
+    }
+}
+
+/// Contents of a previously published file, if there is one
+async fn read_published(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    match fs::read(path).await {
+        Ok(published) => Ok(Some(published)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+impl Publisher for LocalDirectory {
+    fn publish<'a>(
+        &'a self,
+        release: &'a Release,
+        wheel_paths: &'a [PathBuf],
+        hashes: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.publish_release(release, wheel_paths, hashes).boxed()
+    }
+
+    fn published_versions<'a>(
+        &'a self,
+        project: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<HashSet<String>>> {
+        self.versions(project).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn copy_wheels_and_hashes() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let built = temp_dir.path().join("dist");
+        fs::create_dir_all(&built).await?;
+        let mut wheel_paths = vec![];
+        for name in ["a.whl", "b.whl"] {
+            let wheel_path = built.join(name);
+            fs::write(&wheel_path, name).await?;
+            wheel_paths.push(wheel_path);
+        }
+        let provenance = built.join("regex-2024.11.6.intoto.jsonl");
+        fs::write(&provenance, "{}").await?;
+        let publisher = LocalDirectory::new(temp_dir.path().join("published"));
+        let mut release = Release {
+            project: "regex".to_owned(),
+            version: "2024.11.6".to_owned(),
+            notes: String::new(),
+            replace_existing_release: false,
+            manifest: ReleaseManifest {
+                project: "regex".to_owned(),
+                version: "2024.11.6".to_owned(),
+                wheels: vec![],
+            },
+            attachments: vec![provenance],
+        };
+        let dir = temp_dir.path().join("published/regex/2024.11.6");
+
+        publisher
+            .publish(&release, &wheel_paths[..1], "1111\ta.whl\n")
+            .await?;
+        publisher
+            .publish(&release, &wheel_paths[1..], "2222\tb.whl\n")
+            .await?;
+        assert_eq!(fs::read_to_string(dir.join("b.whl")).await?, "b.whl");
+        // Each publish appends its provenance
+        assert_eq!(
+            fs::read_to_string(dir.join("regex-2024.11.6.intoto.jsonl")).await?,
+            "{}\n{}"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join(HASHES_FILE)).await?,
+            "1111\ta.whl\n2222\tb.whl\n"
+        );
+        assert_eq!(
+            publisher.published_versions("regex").await?,
+            HashSet::from(["2024.11.6".to_owned()])
+        );
+        assert!(publisher.published_versions("numpy").await?.is_empty());
+        assert_eq!(
+            ReleaseManifest::parse(&fs::read(dir.join(MANIFEST_FILE)).await?)?,
+            release.manifest
+        );
+
+        release.replace_existing_release = true;
+        publisher
+            .publish(&release, &wheel_paths[1..], "3333\tb.whl\n")
+            .await?;
+        assert!(!dir.join("a.whl").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join(HASHES_FILE)).await?,
+            "3333\tb.whl\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1043 @@
+//! Publish wheels as GitHub releases through the REST API.
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use futures_util::{FutureExt, future::BoxFuture};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, header};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::fs;
+
+use crate::publish::{
+    MANIFEST_FILE, Publisher, Release, ReleaseManifest, append_attachment, file_name,
+    format_hashes, parse_body_hashes, parse_hashes, replace_body_hashes,
+};
+
+/// GitHub API used unless another one is configured
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// How many releases to request at once when listing them, the most the API allows
+const RELEASES_PER_PAGE: usize = 100;
+
+/// Suffix of the temporary name a replacement asset is uploaded under
+const UPLOADING_SUFFIX: &str = ".uploading";
+
+/// How many times to try renaming a replacement asset once the old one is deleted
+const RENAME_ATTEMPTS: usize = 3;
+
+/// Creates and replaces releases in a GitHub repository
+#[derive(Debug, Clone)]
+pub struct GitHubReleases {
+    client: Client,
+    /// Base URL of the API, without a trailing slash
+    api_url: String,
+    /// Repository the releases belong to: `<owner>/<repo>`
+    repo: String,
+    /// Token to authenticate with, if any
+    token: Option<String>,
+}
+
+/// The parts of a release needed to publish to it
+#[derive(Debug, Deserialize)]
+struct GitHubRelease {
+    id: u64,
+    /// URL template for uploading assets, i.e. `.../assets{?name,label}`
+    upload_url: String,
+    /// Release notes, with the hashes of the wheels
+    body: Option<String>,
+    /// Files already uploaded
+    #[serde(default)]
+    assets: Vec<ReleaseAsset>,
+}
+
+/// The tag of a release, when listing them
+#[derive(Debug, Deserialize)]
+struct ReleaseTag {
+    tag_name: String,
+}
+
+/// A file uploaded to a release
+#[derive(Debug, Deserialize)]
+struct ReleaseAsset {
+    id: u64,
+    name: String,
+    /// `sha256:<hash>` of the file, for assets uploaded since GitHub started recording it
+    digest: Option<String>,
+}
+
+/// A request to the GitHub API that didn't succeed, with the message GitHub responded with.
+/// Can be found with `anyhow::Error::downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHubError {
+    /// URL of the request
+    pub url: String,
+    /// Status of the response
+    pub status: StatusCode,
+    /// Message from the response body
+    pub message: String,
+}
+
+impl fmt::Display for GitHubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "GitHub API request to {} failed with {}: {}",
+            self.url, self.status, self.message
+        )
+    }
+}
+
+impl std::error::Error for GitHubError {}
+
+/// The body of an unsuccessful response
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Split a repo argument into its owner and name
+///
+/// # Errors
+/// If it isn't of the form `<owner>/<repo>`.
+pub fn split_repo(repo: &str) -> anyhow::Result<(&str, &str)> {
+    repo.split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("Invalid repo argument of type <owner>/<repo>"))
+}
+
+impl GitHubReleases {
+    /// Client for the releases of the given `<owner>/<repo>`.
+    ///
+    /// Without an API URL, uses `GITHUB_API_URL` or the public GitHub API. Authenticates with
+    /// `GH_TOKEN` or `GITHUB_TOKEN`, if set.
+    ///
+    /// # Errors
+    /// If the repo is invalid or the HTTP client can't be created.
+    pub fn new(repo: &str, api_url: Option<&str>) -> anyhow::Result<Self> {
+        split_repo(repo)?;
+        let api_url = api_url
+            .map(ToOwned::to_owned)
+            .or_else(|| env::var("GITHUB_API_URL").ok())
+            .unwrap_or_else(|| DEFAULT_API_URL.to_owned());
+
+        Ok(Self {
+            client: Client::builder().use_rustls_tls().build()?,
+            api_url: api_url.trim_end_matches('/').to_owned(),
+            repo: repo.to_owned(),
+            token: env::var("GH_TOKEN")
+                .or_else(|_| env::var("GITHUB_TOKEN"))
+                .ok(),
+        })
+    }
+
+    /// Create a release for the tag with the given notes, and upload the files to it along with
+    /// the manifest and attachments. `hashes` has a `<sha256>\t<filename>` line for each file,
+    /// and is added to the notes.
+    ///
+    /// If the release already exists, only files that are missing or whose hash changed are
+    /// uploaded, the hashes in its notes and its manifest are updated, and the attachments are
+    /// appended to those already uploaded. Replaced files are uploaded under a temporary name
+    /// first, so a failed upload leaves the old one in place. With `replace_existing_release`,
+    /// the release is deleted along with its tag and created again instead.
+    ///
+    /// # Errors
+    /// If any of the requests fail, or the files can't be read.
+    pub async fn publish_release(
+        &self,
+        release: &Release,
+        files: &[impl AsRef<Path>],
+        hashes: &str,
+    ) -> anyhow::Result<()> {
+        let tag = release.tag();
+        match self.release_by_tag(&tag).await? {
+            Some(existing) if !release.replace_existing_release => {
+                self.update_release(&existing, files, hashes, &release.manifest)
+                    .await?;
+                for attachment in &release.attachments {
+                    let name = file_name(attachment)?;
+                    match existing.assets.iter().find(|asset| asset.name == name) {
+                        Some(asset) => self.append_asset(&existing, asset, attachment).await?,
+                        None => self.upload_asset(&existing, attachment).await?,
+                    }
+                }
+                return Ok(());
+            }
+            Some(existing) => self.delete_release(&existing, &tag).await?,
+            None => {}
+        }
+
+        let created = self
+            .create_release(&tag, &replace_body_hashes(&release.notes, hashes))
+            .await?;
+        for file in files {
+            self.upload_asset(&created, file.as_ref()).await?;
+        }
+        for attachment in &release.attachments {
+            self.upload_asset(&created, attachment).await?;
+        }
+        self.upload(
+            &created,
+            MANIFEST_FILE,
+            content_type(Path::new(MANIFEST_FILE)),
+            release.manifest.to_json()?,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Upload the files that are missing from the release or changed, and update the hashes in
+    /// its notes and its manifest
+    async fn update_release(
+        &self,
+        release: &GitHubRelease,
+        files: &[impl AsRef<Path>],
+        hashes: &str,
+        manifest: &ReleaseManifest,
+    ) -> anyhow::Result<()> {
+        let hashes = parse_hashes(hashes);
+        let body = release.body.clone().unwrap_or_default();
+        let published = parse_body_hashes(&body).unwrap_or_default();
+        // Keep the hashes of assets that aren't being published again
+        let mut body_hashes = release
+            .assets
+            .iter()
+            .filter_map(|asset| {
+                let hash = published.get(&asset.name)?;
+                Some((asset.name.clone(), hash.clone()))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        for file in files {
+            let file = file.as_ref();
+            let name = file_name(file)?;
+            let hash = hashes
+                .get(name)
+                .with_context(|| format!("Missing hash for {name}"))?;
+
+            match release.assets.iter().find(|asset| asset.name == name) {
+                Some(asset) if asset_hash(asset, &published) == Some(hash.as_str()) => {
+                    println!("{name} is already published");
+                }
+                Some(asset) => {
+                    println!("Replacing {name}, which changed");
+                    self.replace_asset(release, asset, file).await?;
+                }
+                None => {
+                    println!("Uploading {name}");
+                    self.upload_asset(release, file).await?;
+                }
+            }
+            body_hashes.insert(name.to_owned(), hash.clone());
+        }
+
+        let updated = replace_body_hashes(&body, &format_hashes(&body_hashes));
+        if updated != body {
+            check(
+                self.request(Method::PATCH, &format!("releases/{}", release.id))
+                    .json(&json!({ "body": updated }))
+                    .send()
+                    .await?,
+            )
+            .await?;
+        }
+
+        self.update_manifest(release, manifest).await
+    }
+
+    /// Add the wheels of the release's manifest that weren't published again to the manifest,
+    /// and upload it if it changed
+    async fn update_manifest(
+        &self,
+        release: &GitHubRelease,
+        manifest: &ReleaseManifest,
+    ) -> anyhow::Result<()> {
+        let existing = release
+            .assets
+            .iter()
+            .find(|asset| asset.name == MANIFEST_FILE);
+        let Some(existing) = existing else {
+            self.upload(
+                release,
+                MANIFEST_FILE,
+                content_type(Path::new(MANIFEST_FILE)),
+                manifest.to_json()?,
+            )
+            .await?;
+            return Ok(());
+        };
+
+        let published = ReleaseManifest::parse(&self.download_asset(existing).await?)?;
+        let merged = manifest.merge(&published);
+        if merged == published {
+            return Ok(());
+        }
+        self.replace(
+            release,
+            existing,
+            content_type(Path::new(MANIFEST_FILE)),
+            merged.to_json()?,
+        )
+        .await
+    }
+
+    /// Request to the given path of the repository's API
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.authenticated(self.client.request(
+            method,
+            format!("{}/repos/{}/{path}", self.api_url, self.repo),
+        ))
+        .header(header::ACCEPT, "application/vnd.github+json")
+    }
+
+    /// Add the headers every API request needs
+    fn authenticated(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = request
+            .header(header::USER_AGENT, "wasi-wheels")
+            .header("X-GitHub-Api-Version", "2022-11-28");
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Versions of the project with a `<project>/v<version>` release
+    async fn versions(&self, project: &str) -> anyhow::Result<HashSet<String>> {
+        let prefix = format!("{project}/v");
+        let mut versions = HashSet::new();
+        for page in 1.. {
+            let releases = check(
+                self.request(Method::GET, "releases")
+                    .query(&[("per_page", RELEASES_PER_PAGE), ("page", page)])
+                    .send()
+                    .await?,
+            )
+            .await?
+            .json::<Vec<ReleaseTag>>()
+            .await?;
+            versions.extend(
+                releases
+                    .iter()
+                    .filter_map(|release| release.tag_name.strip_prefix(&prefix))
+                    .map(ToOwned::to_owned),
+            );
+            if releases.len() < RELEASES_PER_PAGE {
+                break;
+            }
+        }
+        Ok(versions)
+    }
+
+    /// The release for the tag, if there is one
+    async fn release_by_tag(&self, tag: &str) -> anyhow::Result<Option<GitHubRelease>> {
+        let response = self
+            .request(Method::GET, &format!("releases/tags/{tag}"))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(check(response).await?.json().await?))
+    }
+
+    /// Delete the release and its tag
+    async fn delete_release(&self, release: &GitHubRelease, tag: &str) -> anyhow::Result<()> {
+        check(
+            self.request(Method::DELETE, &format!("releases/{}", release.id))
+                .send()
+                .await?,
+        )
+        .await?;
+
+        let response = self
+            .request(Method::DELETE, &format!("git/refs/tags/{tag}"))
+            .send()
+            .await?;
+        // The tag may never have been pushed
+        if response.status() != StatusCode::UNPROCESSABLE_ENTITY {
+            check(response).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Create a release, and its tag if needed
+    async fn create_release(&self, tag: &str, notes: &str) -> anyhow::Result<GitHubRelease> {
+        let response = self
+            .request(Method::POST, "releases")
+            .json(&json!({ "tag_name": tag, "name": tag, "body": notes }))
+            .send()
+            .await?;
+        Ok(check(response).await?.json().await?)
+    }
+
+    /// Delete a file from a release
+    async fn delete_asset(&self, asset: &ReleaseAsset) -> anyhow::Result<()> {
+        check(
+            self.request(Method::DELETE, &format!("releases/assets/{}", asset.id))
+                .send()
+                .await?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Contents of a file uploaded to a release
+    async fn download_asset(&self, asset: &ReleaseAsset) -> anyhow::Result<Vec<u8>> {
+        let response = self
+            .authenticated(self.client.get(format!(
+                "{}/repos/{}/releases/assets/{}",
+                self.api_url, self.repo, asset.id
+            )))
+            .header(header::ACCEPT, "application/octet-stream")
+            .send()
+            .await?;
+        Ok(check(response)
+            .await
+            .with_context(|| format!("Unable to download {}", asset.name))?
+            .bytes()
+            .await?
+            .to_vec())
+    }
+
+    /// Upload a file to the release, named after the file
+    async fn upload_asset(&self, release: &GitHubRelease, path: &Path) -> anyhow::Result<()> {
+        self.upload(
+            release,
+            file_name(path)?,
+            content_type(path),
+            fs::read(path).await?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Replace an asset of the release with a file
+    async fn replace_asset(
+        &self,
+        release: &GitHubRelease,
+        asset: &ReleaseAsset,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        self.replace(release, asset, content_type(path), fs::read(path).await?)
+            .await
+    }
+
+    /// Replace an asset of the release with its contents followed by those of a file
+    async fn append_asset(
+        &self,
+        release: &GitHubRelease,
+        asset: &ReleaseAsset,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        let published = self.download_asset(asset).await?;
+        self.replace(
+            release,
+            asset,
+            content_type(path),
+            append_attachment(&published, &fs::read(path).await?),
+        )
+        .await
+    }
+
+    /// Replace an asset of the release with the contents. They are uploaded under a temporary
+    /// name, and only renamed once the old asset is deleted, so a failed upload doesn't leave
+    /// the release without it. A temporary asset left over from an earlier run is deleted first.
+    ///
+    /// If renaming still fails after a few attempts, the error names the temporary asset, which
+    /// holds the new contents and only needs to be renamed.
+    async fn replace(
+        &self,
+        release: &GitHubRelease,
+        asset: &ReleaseAsset,
+        content_type: &str,
+        contents: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let temporary = format!("{}{UPLOADING_SUFFIX}", asset.name);
+        if let Some(stale) = release.assets.iter().find(|stale| stale.name == temporary) {
+            self.delete_asset(stale).await?;
+        }
+        let uploaded = self
+            .upload(release, &temporary, content_type, contents)
+            .await?
+            .json::<ReleaseAsset>()
+            .await?;
+        self.delete_asset(asset).await?;
+
+        let mut renamed = self.rename_asset(&uploaded, &asset.name).await;
+        for _ in 1..RENAME_ATTEMPTS {
+            if renamed.is_ok() {
+                break;
+            }
+            renamed = self.rename_asset(&uploaded, &asset.name).await;
+        }
+        renamed.with_context(|| {
+            format!(
+                "{} was replaced, but its new contents are still named {} (asset {}). Rename it \
+                 to {} to restore it",
+                asset.name, uploaded.name, uploaded.id, asset.name
+            )
+        })
+    }
+
+    /// Give an asset of a release a new name
+    async fn rename_asset(&self, asset: &ReleaseAsset, name: &str) -> anyhow::Result<()> {
+        check(
+            self.request(Method::PATCH, &format!("releases/assets/{}", asset.id))
+                .json(&json!({ "name": name }))
+                .send()
+                .await?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Upload contents to the release as a file with the given name
+    async fn upload(
+        &self,
+        release: &GitHubRelease,
+        name: &str,
+        content_type: &str,
+        contents: Vec<u8>,
+    ) -> anyhow::Result<Response> {
+        let upload_url = release
+            .upload_url
+            .split_once('{')
+            .map_or(release.upload_url.as_str(), |(url, _)| url);
+
+        let response = self
+            .authenticated(self.client.post(upload_url))
+            .query(&[("name", name)])
+            .header(header::ACCEPT, "application/vnd.github+json")
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CONTENT_LENGTH, contents.len())
+            .body(contents)
+            .send()
+            .await?;
+        check(response)
+            .await
+            .with_context(|| format!("Unable to upload {name}"))
+    }
+}
+
+impl Publisher for GitHubReleases {
+    fn publish<'a>(
+        &'a self,
+        release: &'a Release,
+        wheel_paths: &'a [PathBuf],
+        hashes: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.publish_release(release, wheel_paths, hashes).boxed()
+    }
+
+    fn published_versions<'a>(
+        &'a self,
+        project: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<HashSet<String>>> {
+        self.versions(project).boxed()
+    }
+}
+
+/// Hash of a published asset, from the release notes or the digest GitHub recorded
+fn asset_hash<'a>(
+    asset: &'a ReleaseAsset,
+    published: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+    published
+        .get(&asset.name)
+        .map(String::as_str)
+        .or_else(|| asset.digest.as_deref()?.strip_prefix("sha256:"))
+}
+
+/// Content type to upload a file with, based on its extension
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(OsStr::to_str) {
+        // Wheels are zip archives
+        Some("whl" | "zip") => "application/zip",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Turn an unsuccessful response into an error with the message from GitHub
+async fn check(response: Response) -> Result<Response, GitHubError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let url = response.url().to_string();
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<ErrorResponse>(&body).map_or(body, |error| error.message);
+    Err(GitHubError {
+        url,
+        status,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        Match, Mock, MockServer, ResponseTemplate,
+        matchers::{any, body_partial_json, body_string, header, method, path, query_param},
+    };
+
+    use crate::WheelManifest;
+
+    use super::*;
+
+    /// Hashes of the wheel written by [`wheel`]
+    const HASHES: &str = "abc\tregex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl\n";
+
+    /// A release as returned by the API, uploading to the mock server
+    fn release(server: &MockServer, id: u64) -> serde_json::Value {
+        json!({
+            "id": id,
+            "upload_url": format!("{}/uploads/releases/{id}/assets{{?name,label}}", server.uri()),
+        })
+    }
+
+    /// A release of regex to publish, with the given wheels in its manifest
+    fn regex_release(version: &str, replace_existing_release: bool, wheels: &[&str]) -> Release {
+        Release {
+            project: "regex".to_owned(),
+            version: version.to_owned(),
+            notes: "notes".to_owned(),
+            replace_existing_release,
+            manifest: ReleaseManifest {
+                project: "regex".to_owned(),
+                version: version.to_owned(),
+                wheels: wheels
+                    .iter()
+                    .map(|&filename| WheelManifest {
+                        filename: filename.to_owned(),
+                        sha256: "5555".to_owned(),
+                        size: 5,
+                        python_version: None,
+                        cpython_version: None,
+                        wasi_sdk_version: None,
+                        wasi_wheels_version: "0.1.0".to_owned(),
+                        build_flags: json!({}),
+                    })
+                    .collect(),
+            },
+            attachments: vec![],
+        }
+    }
+
+    async fn wheel(dir: &Path) -> anyhow::Result<std::path::PathBuf> {
+        let wheel = dir.join("regex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl");
+        fs::write(&wheel, "wheel").await?;
+        Ok(wheel)
+    }
+
+    #[tokio::test]
+    async fn create_release_with_assets() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        let temp_dir = tempfile::tempdir()?;
+        let wheel = wheel(temp_dir.path()).await?;
+
+        Mock::given(method("GET"))
+            .and(path("/repos/owner/repo/releases/tags/regex/v2024.11.6"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/repos/owner/repo/releases"))
+            .and(body_partial_json(json!({
+                "tag_name": "regex/v2024.11.6",
+                "name": "regex/v2024.11.6",
+                "body": "notes\n\n### SHA256 Hashes\n\n```\nabc\tregex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl\n```",
+            })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(release(&server, 1)))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/uploads/releases/1/assets"))
+            .and(query_param(
+                "name",
+                "regex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl",
+            ))
+            .and(header("content-type", "application/zip"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/uploads/releases/1/assets"))
+            .and(query_param("name", MANIFEST_FILE))
+            .and(header("content-type", "application/json"))
+            .and(body_partial_json(json!({ "project": "regex" })))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        GitHubReleases::new("owner/repo", Some(&server.uri()))?
+            .publish_release(&regex_release("2024.11.6", false, &[]), &[wheel], HASHES)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replace_existing_release() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        let temp_dir = tempfile::tempdir()?;
+        let wheel = wheel(temp_dir.path()).await?;
+        let releases = GitHubReleases::new("owner/repo", Some(&server.uri()))?;
+
+        Mock::given(method("GET"))
+            .and(path("/repos/owner/repo/releases/tags/regex/v2024.11.6"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(release(&server, 7)))
+            .mount(&server)
+            .await;
+
+        Mock::given(method("DELETE"))
+            .and(path("/repos/owner/repo/releases/7"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/repos/owner/repo/git/refs/tags/regex/v2024.11.6"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/repos/owner/repo/releases"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(release(&server, 8)))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // The wheel and the manifest
+        Mock::given(method("POST"))
+            .and(path("/uploads/releases/8/assets"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        releases
+            .publish_release(&regex_release("2024.11.6", true, &[]), &[&wheel], HASHES)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Expect the asset `old_id` of release 3 to be deleted and replaced by one with the given id,
+    /// with contents matching `contents`
+    async fn mock_replace(
+        server: &MockServer,
+        old_id: u64,
+        id: u64,
+        name: &str,
+        contents: impl Match + 'static,
+    ) {
+        Mock::given(method("DELETE"))
+            .and(path(format!("/repos/owner/repo/releases/assets/{old_id}")))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(server)
+            .await;
+        let temporary = format!("{name}.uploading");
+        Mock::given(method("POST"))
+            .and(path("/uploads/releases/3/assets"))
+            .and(query_param("name", temporary.as_str()))
+            .and(contents)
+            .respond_with(
+                ResponseTemplate::new(201).set_body_json(json!({ "id": id, "name": temporary })),
+            )
+            .expect(1)
+            .mount(server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path(format!("/repos/owner/repo/releases/assets/{id}")))
+            .and(body_partial_json(json!({ "name": name })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    /// Expect an asset to be downloaded once
+    async fn mock_download(server: &MockServer, id: u64, response: ResponseTemplate) {
+        Mock::given(method("GET"))
+            .and(path(format!("/repos/owner/repo/releases/assets/{id}")))
+            .and(header("accept", "application/octet-stream"))
+            .respond_with(response)
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn upload_only_changed_assets() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        let temp_dir = tempfile::tempdir()?;
+        let mut files = vec![];
+        for name in ["a.whl", "b.whl", "d.whl"] {
+            let file = temp_dir.path().join(name);
+            fs::write(&file, name).await?;
+            files.push(file);
+        }
+
+        let mut existing = release(&server, 3);
+        existing["body"] =
+            json!("notes\n\n### SHA256 Hashes\n\n```\n1111\ta.whl\n2222\tb.whl\n3333\tc.whl\n```");
+        existing["assets"] = json!([
+            { "id": 10, "name": "a.whl" },
+            { "id": 11, "name": "b.whl", "digest": "sha256:2222" },
+            { "id": 12, "name": "c.whl" },
+            { "id": 13, "name": "manifest.json" },
+            { "id": 14, "name": "regex-1.intoto.jsonl" },
+        ]);
+        let attachment = temp_dir.path().join("regex-1.intoto.jsonl");
+        fs::write(&attachment, "{\"run\":2}\n").await?;
+        let published = regex_release("1", false, &["c.whl"]).manifest;
+        Mock::given(method("GET"))
+            .and(path("/repos/owner/repo/releases/tags/regex/v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(existing))
+            .mount(&server)
+            .await;
+        mock_download(
+            &server,
+            13,
+            ResponseTemplate::new(200).set_body_json(&published),
+        )
+        .await;
+        mock_download(
+            &server,
+            14,
+            ResponseTemplate::new(200).set_body_string("{\"run\":1}\n"),
+        )
+        .await;
+        // Changed files are uploaded under a temporary name, and renamed once the old one is gone
+        mock_replace(
+            &server,
+            13,
+            23,
+            MANIFEST_FILE,
+            body_partial_json(regex_release("1", false, &["c.whl", "d.whl"]).manifest),
+        )
+        .await;
+        mock_replace(&server, 11, 21, "b.whl", any()).await;
+        // Attachments keep what was published before
+        mock_replace(
+            &server,
+            14,
+            24,
+            "regex-1.intoto.jsonl",
+            body_string("{\"run\":1}\n{\"run\":2}\n"),
+        )
+        .await;
+        for (name, uploads) in [
+            ("a.whl", 0),
+            ("b.whl", 0),
+            ("d.whl", 1),
+            ("regex-1.intoto.jsonl", 0),
+        ] {
+            Mock::given(method("POST"))
+                .and(path("/uploads/releases/3/assets"))
+                .and(query_param("name", name))
+                .respond_with(ResponseTemplate::new(201))
+                .expect(uploads)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("PATCH"))
+            .and(path("/repos/owner/repo/releases/3"))
+            .and(body_partial_json(json!({
+                "body": "notes\n\n### SHA256 Hashes\n\n```\n1111\ta.whl\n4444\tb.whl\n3333\tc.whl\n5555\td.whl\n```",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        GitHubReleases::new("owner/repo", Some(&server.uri()))?
+            .publish_release(
+                &Release {
+                    attachments: vec![attachment],
+                    ..regex_release("1", false, &["d.whl"])
+                },
+                &files,
+                "1111\ta.whl\n4444\tb.whl\n5555\td.whl\n",
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keep_changed_assets_if_upload_fails() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        let temp_dir = tempfile::tempdir()?;
+        let wheel = wheel(temp_dir.path()).await?;
+
+        let mut existing = release(&server, 3);
+        existing["assets"] = json!([
+            { "id": 10, "name": "regex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl" },
+        ]);
+        Mock::given(method("GET"))
+            .and(path("/repos/owner/repo/releases/tags/regex/v2024.11.6"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(existing))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/uploads/releases/3/assets"))
+            .respond_with(ResponseTemplate::new(502))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        assert!(
+            GitHubReleases::new("owner/repo", Some(&server.uri()))?
+                .publish_release(&regex_release("2024.11.6", false, &[]), &[wheel], HASHES)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_published_versions() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        // A full first page, so the next one is requested too
+        let first_page = (1..RELEASES_PER_PAGE)
+            .map(|patch| json!({ "tag_name": format!("numpy/v2.0.{patch}") }))
+            .chain([json!({ "tag_name": "regex/v2024.11.6" })])
+            .collect::<Vec<_>>();
+        for (page, releases) in [
+            ("1", json!(first_page)),
+            ("2", json!([{ "tag_name": "regex/v2024.9.11" }])),
+        ] {
+            Mock::given(method("GET"))
+                .and(path("/repos/owner/repo/releases"))
+                .and(query_param("per_page", "100"))
+                .and(query_param("page", page))
+                .respond_with(ResponseTemplate::new(200).set_body_json(releases))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let versions = GitHubReleases::new("owner/repo", Some(&server.uri()))?
+            .published_versions("regex")
+            .await?;
+        assert_eq!(
+            versions,
+            HashSet::from(["2024.11.6".to_owned(), "2024.9.11".to_owned()])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recover_from_earlier_failed_replacements() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        let temp_dir = tempfile::tempdir()?;
+        let wheel = wheel(temp_dir.path()).await?;
+        let name = "regex-2024.11.6-cp312-cp312-wasi_0_0_0_wasm32.whl";
+
+        let mut existing = release(&server, 3);
+        existing["assets"] = json!([
+            { "id": 10, "name": name },
+            { "id": 15, "name": format!("{name}.uploading") },
+        ]);
+        Mock::given(method("GET"))
+            .and(path("/repos/owner/repo/releases/tags/regex/v2024.11.6"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(existing))
+            .mount(&server)
+            .await;
+        // The stale temporary asset would clash with the new one
+        Mock::given(method("DELETE"))
+            .and(path("/repos/owner/repo/releases/assets/15"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/repos/owner/repo/releases/assets/10"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/uploads/releases/3/assets"))
+            .and(query_param("name", format!("{name}.uploading")))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(json!({ "id": 20, "name": format!("{name}.uploading") })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/repos/owner/repo/releases/assets/20"))
+            .respond_with(ResponseTemplate::new(502))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let error = GitHubReleases::new("owner/repo", Some(&server.uri()))?
+            .publish_release(&regex_release("2024.11.6", false, &[]), &[wheel], HASHES)
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains(&format!("still named {name}.uploading (asset 20)")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn report_api_errors() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "message": "Bad credentials",
+                "documentation_url": "https://docs.github.com/rest",
+            })))
+            .mount(&server)
+            .await;
+
+        let error = GitHubReleases::new("owner/repo", Some(&server.uri()))?
+            .publish_release(&regex_release("1", false, &[]), &[] as &[&Path], "")
+            .await
+            .unwrap_err();
+        let error = error.downcast_ref::<GitHubError>().unwrap();
+        assert_eq!(error.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.message, "Bad credentials");
+        assert!(error.to_string().contains("401"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(
+            content_type(Path::new("a-1.0-py3-none-any.whl")),
+            "application/zip"
+        );
+        assert_eq!(content_type(Path::new("manifest.json")), "application/json");
+        assert_eq!(content_type(Path::new("hashes.txt")), "text/plain");
+        assert_eq!(
+            content_type(Path::new("archive")),
+            "application/octet-stream"
+        );
+    }
+}
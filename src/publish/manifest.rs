@@ -0,0 +1,136 @@
+//! Machine readable description of the wheels in a release.
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the manifest published with each release
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Every wheel published for a release, and what it was built with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReleaseManifest {
+    /// Project the wheels were built for
+    pub project: String,
+    /// Version the wheels were built from
+    pub version: String,
+    /// Wheels in the release, sorted by filename
+    pub wheels: Vec<WheelManifest>,
+}
+
+/// A single wheel in a release
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WheelManifest {
+    /// Name of the wheel file
+    pub filename: String,
+    /// Sha256 of the wheel file
+    pub sha256: String,
+    /// Size of the wheel file in bytes
+    pub size: u64,
+    /// Python version the wheel was built for, i.e. `3.12`. Not set for pure Python wheels.
+    pub python_version: Option<String>,
+    /// Exact `CPython` version compiled for WASI. Not set for pure Python wheels.
+    pub cpython_version: Option<String>,
+    /// WASI SDK version used to compile extension modules. Not set for pure Python wheels.
+    pub wasi_sdk_version: Option<String>,
+    /// Version of this tool that built the wheel
+    pub wasi_wheels_version: String,
+    /// Environment variables, arguments and generated cross or toolchain files of the command
+    /// that built the wheel
+    pub build_flags: serde_json::Value,
+}
+
+impl ReleaseManifest {
+    /// Parse a published manifest
+    ///
+    /// # Errors
+    /// If the manifest isn't valid JSON.
+    pub fn parse(manifest: &[u8]) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(manifest)?)
+    }
+
+    /// Hashes by filename of every wheel in the manifest
+    #[must_use]
+    pub fn hashes(&self) -> HashMap<String, String> {
+        self.wheels
+            .iter()
+            .map(|wheel| (wheel.filename.clone(), wheel.sha256.clone()))
+            .collect()
+    }
+
+    /// Add the wheels of a previously published manifest that aren't being published again
+    pub(crate) fn merge(&self, published: &Self) -> Self {
+        let republished = self.hashes();
+        let mut merged = self.clone();
+        merged.wheels.extend(
+            published
+                .wheels
+                .iter()
+                .filter(|wheel| !republished.contains_key(&wheel.filename))
+                .cloned(),
+        );
+        merged.wheels.sort_by(|a, b| a.filename.cmp(&b.filename));
+        merged
+    }
+
+    /// The manifest as pretty printed JSON, to publish
+    pub(crate) fn to_json(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn wheel(filename: &str, sha256: &str) -> WheelManifest {
+        WheelManifest {
+            filename: filename.to_owned(),
+            sha256: sha256.to_owned(),
+            size: 5,
+            python_version: Some("3.12".to_owned()),
+            cpython_version: Some("3.12.9".to_owned()),
+            wasi_sdk_version: Some("24".to_owned()),
+            wasi_wheels_version: "0.1.0".to_owned(),
+            build_flags: json!({ "backend": "setuptools" }),
+        }
+    }
+
+    #[test]
+    fn merge_published_wheels() -> anyhow::Result<()> {
+        let published = ReleaseManifest {
+            project: "regex".to_owned(),
+            version: "2024.11.6".to_owned(),
+            wheels: vec![wheel("a.whl", "1111"), wheel("c.whl", "3333")],
+        };
+        let built = ReleaseManifest {
+            wheels: vec![wheel("c.whl", "4444"), wheel("b.whl", "2222")],
+            ..published.clone()
+        };
+
+        let merged = built.merge(&ReleaseManifest::parse(&published.to_json()?)?);
+        assert_eq!(
+            merged.wheels,
+            [
+                wheel("a.whl", "1111"),
+                wheel("b.whl", "2222"),
+                wheel("c.whl", "4444")
+            ]
+        );
+        assert_eq!(merged.hashes()["c.whl"], "4444");
+
+        Ok(())
+    }
+
+    #[test]
+    fn kebab_case_fields() -> anyhow::Result<()> {
+        let manifest = serde_json::to_value(wheel("a.whl", "1111"))?;
+        assert_eq!(manifest["cpython-version"], "3.12.9");
+        assert_eq!(manifest["wasi-sdk-version"], "24");
+        assert_eq!(manifest["build-flags"]["backend"], "setuptools");
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+//! Publish wheels to an S3-compatible bucket.
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{Context, bail};
+use futures_util::{FutureExt, TryStreamExt, future::BoxFuture};
+use object_store::{
+    ObjectStore, PutPayload,
+    aws::{AmazonS3, AmazonS3Builder},
+    path::Path as ObjectPath,
+};
+use tokio::fs;
+use url::Url;
+
+use crate::publish::{
+    HASHES_FILE, MANIFEST_FILE, Publisher, Release, ReleaseManifest, append_attachment, file_name,
+    merge_hashes,
+};
+
+/// Uploads wheels to an S3-compatible bucket, under `<prefix>/<project>/<version>` with a
+/// `hashes.txt` listing the hash of each wheel and a `manifest.json`
+#[derive(Debug)]
+pub struct S3Bucket {
+    store: AmazonS3,
+    /// Key prefix to publish under, if any
+    prefix: ObjectPath,
+}
+
+impl S3Bucket {
+    /// Publish to `s3://<bucket>/<prefix>`.
+    ///
+    /// Credentials, region and endpoint are read from the `AWS_*` environment variables, i.e.
+    /// `AWS_ENDPOINT_URL` and `AWS_ALLOW_HTTP` to publish to a local `MinIO`.
+    ///
+    /// # Errors
+    /// If the URL is invalid or the client can't be configured.
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        Self::with_builder(AmazonS3Builder::from_env(), url)
+    }
+
+    /// Publish to `s3://<bucket>/<prefix>` with an otherwise configured client
+    fn with_builder(builder: AmazonS3Builder, url: &str) -> anyhow::Result<Self> {
+        let url = Url::parse(url).with_context(|| format!("Invalid S3 URL {url}"))?;
+        if url.scheme() != "s3" {
+            bail!("Invalid S3 URL {url}, expected s3://<bucket>/<prefix>");
+        }
+        let bucket = url
+            .host_str()
+            .with_context(|| format!("Missing bucket in S3 URL {url}"))?;
+
+        Ok(Self {
+            store: builder.with_bucket_name(bucket).build()?,
+            prefix: ObjectPath::from(url.path()),
+        })
+    }
+
+    /// Upload the wheels into the release's prefix, add them to the hashes file and manifest, and
+    /// append the attachments
+    async fn publish_release(
+        &self,
+        release: &Release,
+        wheel_paths: &[PathBuf],
+        hashes: &str,
+    ) -> anyhow::Result<()> {
+        let prefix = self
+            .prefix
+            .child(release.project.as_str())
+            .child(release.version.as_str());
+        if release.replace_existing_release {
+            let existing = self
+                .store
+                .list(Some(&prefix))
+                .try_collect::<Vec<_>>()
+                .await?;
+            for object in existing {
+                self.store.delete(&object.location).await?;
+            }
+        }
+
+        for path in wheel_paths {
+            let location = prefix.child(file_name(path)?);
+            println!("Uploading {location}");
+            self.store
+                .put(&location, PutPayload::from(fs::read(path).await?))
+                .await?;
+        }
+        for path in &release.attachments {
+            let location = prefix.child(file_name(path)?);
+            println!("Uploading {location}");
+            let published = self.get(&location).await?.unwrap_or_default();
+            self.store
+                .put(
+                    &location,
+                    PutPayload::from(append_attachment(&published, &fs::read(path).await?)),
+                )
+                .await?;
+        }
+
+        let hashes_location = prefix.child(HASHES_FILE);
+        let published = self.get(&hashes_location).await?.unwrap_or_default();
+        self.store
+            .put(
+                &hashes_location,
+                PutPayload::from(merge_hashes(&String::from_utf8(published)?, hashes)),
+            )
+            .await?;
+
+        let manifest_location = prefix.child(MANIFEST_FILE);
+        let manifest = match self.get(&manifest_location).await? {
+            Some(published) => release.manifest.merge(&ReleaseManifest::parse(&published)?),
+            None => release.manifest.clone(),
+        };
+        self.store
+            .put(&manifest_location, PutPayload::from(manifest.to_json()?))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Names of the version prefixes of the project
+    async fn versions(&self, project: &str) -> anyhow::Result<HashSet<String>> {
+        let listed = self
+            .store
+            .list_with_delimiter(Some(&self.prefix.child(project)))
+            .await?;
+        Ok(listed
+            .common_prefixes
+            .iter()
+            .filter_map(|prefix| prefix.filename().map(ToOwned::to_owned))
+            .collect())
+    }
+
+    /// Contents of a previously published object, if there is one
+    async fn get(&self, location: &ObjectPath) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.store.get(location).await {
+            Ok(published) => Ok(Some(published.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl Publisher for S3Bucket {
+    fn publish<'a>(
+        &'a self,
+        release: &'a Release,
+        wheel_paths: &'a [PathBuf],
+        hashes: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.publish_release(release, wheel_paths, hashes).boxed()
+    }
+
+    fn published_versions<'a>(
+        &'a self,
+        project: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<HashSet<String>>> {
+        self.versions(project).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_string, body_string_contains, header_exists, method, path, query_param},
+    };
+
+    use super::*;
+
+    /// Response to uploading an object
+    fn uploaded() -> ResponseTemplate {
+        ResponseTemplate::new(200).insert_header("etag", "\"1\"")
+    }
+
+    /// The `wasi` prefix of the `wheels` bucket on the mock server
+    fn bucket(server: &MockServer) -> anyhow::Result<S3Bucket> {
+        S3Bucket::with_builder(
+            AmazonS3Builder::new()
+                .with_endpoint(server.uri())
+                .with_allow_http(true)
+                .with_region("us-east-1")
+                .with_access_key_id("minioadmin")
+                .with_secret_access_key("minioadmin"),
+            "s3://wheels/wasi",
+        )
+    }
+
+    #[tokio::test]
+    async fn upload_wheels_and_hashes() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        let temp_dir = tempfile::tempdir()?;
+        let wheel_path = temp_dir.path().join("a.whl");
+        fs::write(&wheel_path, "wheel").await?;
+
+        Mock::given(method("PUT"))
+            .and(path("/wheels/wasi/regex/2024.11.6/a.whl"))
+            .and(header_exists("authorization"))
+            .and(body_string("wheel"))
+            .respond_with(uploaded())
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/wheels/wasi/regex/2024.11.6/hashes.txt"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("etag", "\"1\"")
+                    .insert_header("last-modified", "Sat, 17 Oct 2026 00:00:00 GMT")
+                    .set_body_string("1111\tb.whl\n"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/wheels/wasi/regex/2024.11.6/hashes.txt"))
+            .and(body_string("2222\ta.whl\n1111\tb.whl\n"))
+            .respond_with(uploaded())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/wheels/wasi/regex/2024.11.6/manifest.json"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/wheels/wasi/regex/2024.11.6/manifest.json"))
+            .and(body_string_contains("\"project\": \"regex\""))
+            .respond_with(uploaded())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        bucket(&server)?
+            .publish(
+                &Release {
+                    project: "regex".to_owned(),
+                    version: "2024.11.6".to_owned(),
+                    notes: String::new(),
+                    replace_existing_release: false,
+                    manifest: ReleaseManifest {
+                        project: "regex".to_owned(),
+                        version: "2024.11.6".to_owned(),
+                        wheels: vec![],
+                    },
+                    attachments: vec![],
+                },
+                &[wheel_path],
+                "2222\ta.whl\n",
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_published_versions() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/wheels"))
+            .and(query_param("prefix", "wasi/regex/"))
+            .and(query_param("delimiter", "/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<ListBucketResult>\
+                 <CommonPrefixes><Prefix>wasi/regex/2024.9.11/</Prefix></CommonPrefixes>\
+                 <CommonPrefixes><Prefix>wasi/regex/2024.11.6/</Prefix></CommonPrefixes>\
+                 </ListBucketResult>",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(
+            bucket(&server)?.published_versions("regex").await?,
+            HashSet::from(["2024.9.11".to_owned(), "2024.11.6".to_owned()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_urls() {
+        assert!(S3Bucket::with_builder(AmazonS3Builder::new(), "https://wheels/wasi").is_err());
+        assert!(S3Bucket::with_builder(AmazonS3Builder::new(), "wheels").is_err());
+    }
+}
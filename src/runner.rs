@@ -0,0 +1,497 @@
+//! Run external commands. During a build, the output of each command is captured in a log file
+//! per step, and the steps are collected into a build report.
+use std::{
+    fmt,
+    fs::File,
+    future, iter,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use futures_util::{FutureExt, future::BoxFuture};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::{fs, process::Command, signal, time};
+
+use crate::plan;
+
+#[cfg(test)]
+pub mod fake;
+
+/// How many lines of a failed step's log to include in the error
+const LOG_TAIL_LINES: usize = 30;
+
+/// How to run a command
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Kill the command if it takes longer than this
+    pub timeout: Option<Duration>,
+    /// How many more times to try the command if it fails
+    pub retries: u32,
+    /// How long to wait before retrying, doubled after each attempt
+    pub retry_delay: Duration,
+}
+
+impl RunOptions {
+    /// For steps that download things, and so can fail or hang because of the network
+    #[must_use]
+    pub fn network() -> Self {
+        Self {
+            timeout: Some(Duration::from_mins(30)),
+            retries: 3,
+            retry_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Ways running a command can fail that callers may want to handle differently.
+/// Can be found with `anyhow::Error::downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunError {
+    /// Interrupted with Ctrl-C. The command and any processes it started were killed.
+    Cancelled,
+    /// Didn't finish within the timeout, so the command and any processes it started were killed
+    TimedOut(Duration),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::TimedOut(timeout) => write!(f, "timed out after {timeout:?}"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+tokio::task_local! {
+    /// Log of the build running on the current task, if any
+    static BUILD_LOG: BuildLog;
+    /// Runs the commands of the current task, if not the default [`ProcessRunner`]
+    static RUNNER: Arc<dyn CommandRunner>;
+}
+
+/// Captures the output of each step of a build into its own log file
+#[derive(Debug, Clone)]
+pub struct BuildLog {
+    /// Directory the log files and report are written to
+    dir: PathBuf,
+    /// Prefix for the log file names, i.e. which Python version is being built
+    prefix: String,
+    /// Number of steps started so far, to keep log files in order
+    counter: Arc<AtomicUsize>,
+    /// Steps that have finished
+    steps: Arc<Mutex<Vec<Step>>>,
+}
+
+/// A single command run as part of a build
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Step {
+    /// The full command line
+    command: String,
+    /// Directory the command ran in, if not the current one
+    current_dir: Option<PathBuf>,
+    /// How long the command took to run
+    duration_secs: f64,
+    /// Exit code of the command, if it exited normally
+    exit_code: Option<i32>,
+    /// Whether the command succeeded
+    success: bool,
+    /// Log file with the command's stdout and stderr
+    log: PathBuf,
+}
+
+/// Summary of a build, written as JSON next to the step logs
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct BuildReport<'a> {
+    /// Project that was built
+    project: &'a str,
+    /// Whether the build succeeded
+    success: bool,
+    /// Why the build failed, if it did
+    error: Option<String>,
+    /// Every command that ran, in the order they finished
+    steps: Vec<Step>,
+    /// Wheels that were built
+    wheels: Vec<ReportWheel>,
+}
+
+/// A wheel produced by a build
+#[derive(Debug, Serialize)]
+struct ReportWheel {
+    /// Path to the wheel
+    path: PathBuf,
+    /// Sha256 of the wheel contents
+    sha256: String,
+}
+
+impl BuildLog {
+    /// Start a new log in the given directory, replacing any logs from a previous build
+    ///
+    /// # Errors
+    /// If the directory can't be created.
+    pub async fn create(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        if dir.exists() {
+            plan::remove_dir_all(&dir).await?;
+        }
+        plan::create_dir_all(&dir).await?;
+
+        Ok(Self {
+            dir,
+            prefix: String::new(),
+            counter: Arc::default(),
+            steps: Arc::default(),
+        })
+    }
+
+    /// The same log, with a prefix for the log files of the steps
+    #[must_use]
+    pub fn with_prefix(&self, prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Capture the output of any command run within the future in this log
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        BUILD_LOG.scope(self, f).await
+    }
+
+    /// Write the report of the build to `report.json` in the log directory, and return its path
+    ///
+    /// # Errors
+    /// If the wheels can't be read or the report can't be written.
+    pub async fn write_report(
+        &self,
+        project: &str,
+        result: Result<&[PathBuf], &anyhow::Error>,
+    ) -> anyhow::Result<PathBuf> {
+        let mut wheels = vec![];
+        for path in result.unwrap_or_default() {
+            wheels.push(ReportWheel {
+                sha256: format!("{:x}", Sha256::digest(fs::read(path).await?)),
+                path: path.clone(),
+            });
+        }
+
+        let report = BuildReport {
+            project,
+            success: result.is_ok(),
+            error: result.err().map(|error| format!("{error:#}")),
+            steps: self.steps.lock().unwrap().clone(),
+            wheels,
+        };
+        let path = self.dir.join("report.json");
+        fs::write(&path, serde_json::to_string_pretty(&report)?).await?;
+
+        Ok(path)
+    }
+
+    /// Command lines of the steps that have finished so far
+    ///
+    /// # Panics
+    /// If recording a step panicked.
+    #[must_use]
+    pub fn commands(&self) -> Vec<String> {
+        self.steps
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|step| step.command.clone())
+            .collect()
+    }
+
+    /// Run the command with its output captured in a new log file
+    async fn run(
+        &self,
+        command: &mut Command,
+        command_string: &str,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        let number = self.counter.fetch_add(1, Ordering::Relaxed) + 1;
+        let program = Path::new(command.as_std().get_program())
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let log = self
+            .dir
+            .join(format!("{number:02}-{}{program}.log", self.prefix));
+        eprintln!("Running `{command_string}` (log: {})", log.display());
+
+        let file = File::create(&log)?;
+        command
+            .stdout(Stdio::from(file.try_clone()?))
+            .stderr(Stdio::from(file));
+        let start = Instant::now();
+        let status = wait(command, timeout).await;
+
+        self.steps.lock().unwrap().push(Step {
+            command: command_string.to_owned(),
+            current_dir: command.as_std().get_current_dir().map(ToOwned::to_owned),
+            duration_secs: start.elapsed().as_secs_f64(),
+            exit_code: status.as_ref().ok().and_then(ExitStatus::code),
+            success: status.as_ref().is_ok_and(ExitStatus::success),
+            log: log.clone(),
+        });
+
+        let status = status.with_context(|| format!("command `{command_string}`"))?;
+        if status.success() {
+            Ok(())
+        } else {
+            let output = fs::read_to_string(&log).await.unwrap_or_default();
+            bail!(
+                "command `{command_string}` failed with {status}. Last lines of {}:\n{}",
+                log.display(),
+                tail(&output, LOG_TAIL_LINES)
+            );
+        }
+    }
+}
+
+/// Runs commands on behalf of [`run`] and [`run_with`]. Swapped out with [`with_runner`] to
+/// record commands instead, i.e. when planning or in tests.
+pub trait CommandRunner: Send + Sync {
+    /// Run the command once. Retries are handled by the caller.
+    fn run<'a>(
+        &'a self,
+        command: &'a mut Command,
+        command_string: &'a str,
+        timeout: Option<Duration>,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Runs commands as child processes, capturing their output if a build is being logged
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessRunner;
+
+impl CommandRunner for ProcessRunner {
+    fn run<'a>(
+        &'a self,
+        command: &'a mut Command,
+        command_string: &'a str,
+        timeout: Option<Duration>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            if let Ok(log) = BUILD_LOG.try_with(Clone::clone) {
+                return log.run(command, command_string, timeout).await;
+            }
+
+            let status = wait(command, timeout)
+                .await
+                .with_context(|| format!("command `{command_string}`"))?;
+
+            if status.success() {
+                Ok(())
+            } else {
+                bail!("command `{command_string}` failed");
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Run any commands within the future with the given runner
+pub async fn with_runner<F: Future>(runner: Arc<dyn CommandRunner>, f: F) -> F::Output {
+    RUNNER.scope(runner, f).await
+}
+
+/// Run a given command with common error handling behavior
+///
+/// # Errors
+///
+/// Returns error if the command fails for any reason.
+pub async fn run(command: &mut Command) -> anyhow::Result<()> {
+    run_with(command, &RunOptions::default()).await
+}
+
+/// Run a given command with a timeout and retries. Ctrl-C kills the command and any processes
+/// it started, and returns a [`RunError::Cancelled`].
+///
+/// # Errors
+///
+/// Returns error if the command still fails after all retries.
+pub async fn run_with(command: &mut Command, options: &RunOptions) -> anyhow::Result<()> {
+    let command_string = iter::once(command.as_std().get_program())
+        .chain(command.as_std().get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let runner = RUNNER
+        .try_with(Arc::clone)
+        .unwrap_or_else(|_| Arc::new(ProcessRunner));
+
+    let mut delay = options.retry_delay;
+    let mut attempt = 0;
+    loop {
+        let result = runner.run(command, &command_string, options.timeout).await;
+        match result {
+            Err(error)
+                if attempt < options.retries
+                    && error.downcast_ref::<RunError>() != Some(&RunError::Cancelled) =>
+            {
+                attempt += 1;
+                eprintln!(
+                    "{error:#}\nRetrying in {delay:?} ({attempt}/{})",
+                    options.retries
+                );
+                time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Start the command in its own process group and wait for it to exit. On timeout or Ctrl-C,
+/// the whole process group is killed.
+async fn wait(command: &mut Command, timeout: Option<Duration>) -> anyhow::Result<ExitStatus> {
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command.kill_on_drop(true).spawn()?;
+    // Also kills the process group if this future is dropped
+    let mut group = ProcessGroup(child.id());
+
+    let timed_out = async {
+        match timeout {
+            Some(timeout) => time::sleep(timeout).await,
+            None => future::pending().await,
+        }
+    };
+    let status = tokio::select! {
+        status = child.wait() => status?,
+        () = timed_out => return Err(RunError::TimedOut(timeout.unwrap_or_default()).into()),
+        _ = signal::ctrl_c() => return Err(RunError::Cancelled.into()),
+    };
+    // The process exited on its own, so its id may be reused
+    group.0 = None;
+
+    Ok(status)
+}
+
+/// Process group of a running command, killed when dropped
+struct ProcessGroup(Option<u32>);
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.0.and_then(|id| libc::pid_t::try_from(id).ok()) {
+            // SAFETY: only sends a signal, the process group was created for this command
+            unsafe {
+                libc::killpg(pgid, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// The last lines of some output
+fn tail(output: &str, lines: usize) -> String {
+    let all = output.lines().collect::<Vec<_>>();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn capture_step_logs() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let log = BuildLog::create(temp_dir.path().join("logs")).await?;
+
+        let result = log
+            .with_prefix("py3.12-")
+            .scope(async {
+                run(Command::new("sh").args(["-c", "echo hello; echo oops >&2"])).await?;
+                run(Command::new("sh").args(["-c", "seq 1 100; exit 3"])).await?;
+                Ok(vec![])
+            })
+            .await;
+
+        let error = format!("{:#}", result.as_ref().unwrap_err());
+        assert!(error.contains("failed with exit status: 3"));
+        assert!(error.contains("\n71\n") && error.ends_with("\n100"));
+        assert!(!error.contains("\n70\n"));
+
+        let first = temp_dir.path().join("logs/01-py3.12-sh.log");
+        assert_eq!(fs::read_to_string(&first).await?, "hello\noops\n");
+
+        let report = log.write_report("example", result.as_deref()).await?;
+        let report = serde_json::from_str::<serde_json::Value>(&fs::read_to_string(report).await?)?;
+        assert_eq!(report["success"], false);
+        assert_eq!(report["steps"][0]["exit-code"], 0);
+        assert_eq!(report["steps"][0]["log"], first.to_string_lossy().as_ref());
+        assert_eq!(report["steps"][1]["exit-code"], 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn kill_on_timeout() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let marker = temp_dir.path().join("finished");
+        let start = Instant::now();
+
+        let error = run_with(
+            Command::new("sh")
+                .arg("-c")
+                .arg(format!("(sleep 1; touch {}) & wait", marker.display())),
+            &RunOptions {
+                timeout: Some(Duration::from_millis(100)),
+                ..RunOptions::default()
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<RunError>(),
+            Some(&RunError::TimedOut(Duration::from_millis(100)))
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // The background process was killed along with the shell
+        time::sleep(Duration::from_millis(1500)).await;
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn retry_failures() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let attempts = temp_dir.path().join("attempts");
+        let script = format!(
+            "echo x >> {0}; test $(wc -l < {0}) -ge 3",
+            attempts.display()
+        );
+        let options = RunOptions {
+            retries: 2,
+            ..RunOptions::default()
+        };
+
+        run_with(Command::new("sh").args(["-c", &script]), &options).await?;
+        assert_eq!(fs::read_to_string(&attempts).await?.lines().count(), 3);
+
+        fs::remove_file(&attempts).await?;
+        let options = RunOptions {
+            retries: 1,
+            ..RunOptions::default()
+        };
+        assert!(
+            run_with(Command::new("sh").args(["-c", &script]), &options)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
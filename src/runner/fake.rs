@@ -0,0 +1,127 @@
+//! A command runner for tests, which records commands instead of running them.
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use futures_util::{FutureExt, future::BoxFuture};
+use tokio::process::Command;
+
+use super::{CommandRunner, with_runner};
+
+/// Fakes what a command would have done, i.e. writing the wheel it builds
+type SideEffect = Box<dyn Fn(&RecordedCommand) -> anyhow::Result<()> + Send + Sync>;
+
+/// Records commands instead of running them
+#[derive(Default)]
+pub struct RecordingRunner {
+    /// Commands run so far
+    commands: Mutex<Vec<RecordedCommand>>,
+    /// Called for every command, in place of running it
+    side_effect: Option<SideEffect>,
+}
+
+/// A command that would have been run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedCommand {
+    /// Program to run
+    pub program: String,
+    /// Arguments passed to the program
+    pub args: Vec<String>,
+    /// Directory the command runs in, if not the current one
+    pub current_dir: Option<PathBuf>,
+    /// Environment variables set for the command
+    pub env: BTreeMap<String, String>,
+}
+
+impl RecordedCommand {
+    /// The full command line
+    pub fn line(&self) -> String {
+        [self.program.as_str()]
+            .into_iter()
+            .chain(self.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl RecordingRunner {
+    /// Fake what commands do with the given function
+    pub fn with_side_effect(
+        side_effect: impl Fn(&RecordedCommand) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            side_effect: Some(Box::new(side_effect)),
+            ..Self::default()
+        }
+    }
+
+    /// Fake building a wheel: `python -m build` and `maturin` write an empty wheel with the given
+    /// name to the `dist` directory they run in
+    pub fn building(wheel_name: &str) -> Self {
+        let wheel_name = wheel_name.to_owned();
+        Self::with_side_effect(move |command| {
+            let builds = command.program == "maturin"
+                || command
+                    .args
+                    .starts_with(&["-m".to_owned(), "build".to_owned()]);
+            if builds {
+                let dist = command
+                    .current_dir
+                    .as_ref()
+                    .context("Build runs without a directory")?
+                    .join("dist");
+                std::fs::create_dir_all(&dist)?;
+                std::fs::write(dist.join(&wheel_name), "")?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Record the commands run within the future
+    pub async fn record<F: Future>(self: &Arc<Self>, f: F) -> F::Output {
+        with_runner(self.clone(), f).await
+    }
+
+    /// Commands recorded so far
+    pub fn commands(&self) -> Vec<RecordedCommand> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn run<'a>(
+        &'a self,
+        command: &'a mut Command,
+        _command_string: &'a str,
+        _timeout: Option<Duration>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        let command = command.as_std();
+        let recorded = RecordedCommand {
+            program: command.get_program().to_string_lossy().into_owned(),
+            args: command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            current_dir: command.get_current_dir().map(ToOwned::to_owned),
+            env: command
+                .get_envs()
+                .filter_map(|(key, value)| {
+                    Some((
+                        key.to_string_lossy().into_owned(),
+                        value?.to_string_lossy().into_owned(),
+                    ))
+                })
+                .collect(),
+        };
+        let result = self
+            .side_effect
+            .as_ref()
+            .map_or(Ok(()), |side_effect| side_effect(&recorded));
+        self.commands.lock().unwrap().push(recorded);
+        async move { result }.boxed()
+    }
+}
@@ -53,6 +53,18 @@ fn build_pydantic() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn build_unsupported_version() -> anyhow::Result<()> {
+    let assert = Command::cargo_bin("wasi-wheels")?
+        .args(["build", "pydantic-core", "2.0.0"])
+        .assert();
+
+    let output = assert.failure().get_output().clone();
+    assert!(String::from_utf8(output.stderr)?.contains("pydantic-core 2.0.0 is not supported"));
+
+    Ok(())
+}
+
 #[test]
 fn python_component() {
     let path = Path::new("./tests/test-component");